@@ -0,0 +1,41 @@
+// Each mip level is drawn from the one above it with a triangle covering
+// the whole level, one instance per layer
+
+@group(0)
+@binding(0)
+var source: texture_2d<f32>;
+@group(0)
+@binding(1)
+var source_sampler: sampler;
+@group(0)
+@binding(2)
+var source_array: texture_2d_array<f32>;
+
+struct VsOut {
+    @builtin(position)
+    frag_position: vec4<f32>,
+    @location(0)
+    uv: vec2<f32>,
+    @location(1) @interpolate(flat)
+    layer: u32,
+}
+
+@vertex
+fn fullscreen(
+    @builtin(vertex_index) vertex: u32,
+    @builtin(instance_index) layer: u32,
+) -> VsOut {
+    let uv = vec2(f32((vertex << 1u) & 2u), f32(vertex & 2u));
+    let position = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return VsOut(position, uv, layer);
+}
+
+@fragment
+fn downsample(vs: VsOut) -> @location(0) vec4<f32> {
+    return textureSampleLevel(source, source_sampler, vs.uv, 0.0);
+}
+
+@fragment
+fn downsample_array(vs: VsOut) -> @location(0) vec4<f32> {
+    return textureSampleLevel(source_array, source_sampler, vs.uv, vs.layer, 0.0);
+}
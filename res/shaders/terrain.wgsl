@@ -1,15 +1,21 @@
-struct TerrainData {
-    tile_size__mountains__dunes__spires: vec4<f32>,
-}
-
-struct TerrainVertex {
-    position: vec3<f32>,
-    normal: vec3<f32>,
-}
-
+// Heights and normals baked per tile by `terrain_bake.wgsl`.
 @group(0)
-@binding(0)
-var<uniform> terrain_data: TerrainData;
+@binding(1)
+var height_maps: texture_2d_array<f32>;
+@group(0)
+@binding(2)
+var normal_maps: texture_2d_array<f32>;
+@group(0)
+@binding(3)
+var normal_sampler: sampler;
+// Biome weights of each tile, for `biome_blend`.
+@group(0)
+@binding(4)
+var biome_map: texture_2d<f32>;
+// Biome blends baked per tile by `terrain_bake.wgsl`.
+@group(0)
+@binding(5)
+var blend_maps: texture_2d_array<f32>;
 
 struct CameraUniform {
     view_pos: vec4<f32>,
@@ -20,6 +26,8 @@ struct CameraUniform {
 @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Two layers per material: its albedo with its roughness in alpha, then its
+// normal map
 @group(2)
 @binding(0)
 var terrain_textures: texture_2d_array<f32>;
@@ -27,9 +35,54 @@ var terrain_textures: texture_2d_array<f32>;
 @binding(1)
 var terrain_sampler: sampler;
 
+// Where a material covers the terrain. Slopes are in degrees
+struct MaterialRule {
+    min_altitude__max_altitude__altitude_blend: vec3<f32>,
+    noise: f32,
+    min_slope__max_slope__slope_blend: vec3<f32>,
+    // First layer of the material in `terrain_textures`
+    layer: u32,
+    // Multiplied by the biome blend, so that's how much the rule covers
+    biome: vec4<f32>,
+}
+
+struct Materials {
+    spire_layer: u32,
+    rule_count: u32,
+    noise_frequency: f32,
+    // Painted over each other in order
+    rules: array<MaterialRule, 16>,
+}
+@group(2)
+@binding(2)
+var<uniform> materials: Materials;
+// Tiling noise that moves the edges of the rules
+@group(2)
+@binding(3)
+var rule_noise: texture_2d<f32>;
+// Units of noise `rule_noise` covers before repeating. Matches
+// `RULE_NOISE_PERIOD` in `src/game/render/materials.rs`.
+const RULE_NOISE_PERIOD: f32 = 8.0;
+
+struct Lighting {
+    // Towards the sun
+    sun_direction: vec4<f32>,
+    // Already scaled by the sun's intensity
+    sun_color: vec4<f32>,
+    sky_color: vec4<f32>,
+    ground_color: vec4<f32>,
+}
+@group(3)
+@binding(0)
+var<uniform> lighting: Lighting;
+
 struct TileInstance {
     @location(0)
     tile_offset: vec2<f32>,
+    @location(1)
+    lod: u32,
+    @location(2)
+    layer: u32,
 }
 
 struct VsOut {
@@ -42,6 +95,11 @@ struct VsOut {
     world_position: vec3<f32>,
     @location(2)
     world_normal: vec3<f32>,
+    @location(3)
+    tile_uv: vec2<f32>,
+    @location(4)
+    @interpolate(flat)
+    layer: u32,
 }
 
 @vertex
@@ -49,32 +107,104 @@ fn displace_terrain(
    @builtin(vertex_index) index: u32,
    instance: TileInstance,
 ) -> VsOut {
-    let i = f32(index);
-    let num_vertices = terrain_data.tile_size__mountains__dunes__spires.x;
-    let x = floor(i % num_vertices) + instance.tile_offset.x;
-    let z = floor(i / num_vertices) + instance.tile_offset.y;
+    let num_vertices = u32(terrain_data.tile_size__mountains__dunes__spires.x);
+    let grid_size = num_vertices * num_vertices;
 
-    let v = terrain_vertex(vec2(x, z), terrain_data);
+    // Indices past the end of the grid are skirt vertices hanging
+    // below the edge vertex they share a position with.
+    let i = index % grid_size;
+    let is_skirt = index >= grid_size;
 
-    let frag_position = camera.view_proj * vec4(v.position, 1.0);
-    let debug = random_color(vec2(i, 0.0));
+    let grid = vec2(f32(i % num_vertices), f32(i / num_vertices));
+    let local = morph_vertex(grid, instance);
+
+    let height = baked_height(local, instance.layer);
+    var position = vec3(local.x + instance.tile_offset.x, height, local.y + instance.tile_offset.y);
+    if is_skirt {
+        position.y -= terrain_data.lod_distance__morph_start__skirt_depth__lod_count.z;
+    }
+
+    // Filtered at the morphed position, like the height
+    let tile_uv = (local + 0.5) / f32(num_vertices);
+    let normal = textureSampleLevel(normal_maps, normal_sampler, tile_uv, instance.layer, 0.0).xyz;
+
+    let frag_position = camera.view_proj * vec4(position, 1.0);
+    let debug = random_color(vec2(f32(instance.lod), 0.0));
 
     return VsOut(
         frag_position,
         debug,
-        v.position,
-        v.normal,
+        position,
+        normal,
+        tile_uv,
+        instance.layer,
     );
 }
 
+// Bilinearly filters the baked heights. The height maps are 32 bit floats,
+// which can't be filtered by a sampler.
+fn baked_height(local: vec2<f32>, layer: u32) -> f32 {
+    let last = terrain_data.tile_size__mountains__dunes__spires.x - 1.0;
+    let i = min(floor(local), vec2(last - 1.0));
+    let f = local - i;
+    let p = vec2<u32>(i);
+
+    let h00 = textureLoad(height_maps, p, layer, 0).r;
+    let h10 = textureLoad(height_maps, p + vec2(1u, 0u), layer, 0).r;
+    let h01 = textureLoad(height_maps, p + vec2(0u, 1u), layer, 0).r;
+    let h11 = textureLoad(height_maps, p + vec2(1u, 1u), layer, 0).r;
+
+    return mix(mix(h00, h10, f.x), mix(h01, h11, f.x), f.y);
+}
+
+fn baked_normal(vs: VsOut) -> vec3<f32> {
+    return normalize(textureSample(normal_maps, normal_sampler, vs.tile_uv, vs.layer).xyz);
+}
+
+// Whether the baked spire rock in the normal map's alpha covers the
+// fragment.
+fn is_spire(vs: VsOut) -> bool {
+    return textureSample(normal_maps, normal_sampler, vs.tile_uv, vs.layer).w > 0.01;
+}
+
+// Slides vertices that the next level of detail doesn't have onto ones it
+// does as the camera moves away, so switching levels doesn't pop.
+fn morph_vertex(grid: vec2<f32>, instance: TileInstance) -> vec2<f32> {
+    let lod = f32(instance.lod);
+    let lod_distance = terrain_data.lod_distance__morph_start__skirt_depth__lod_count.x;
+    let morph_start = terrain_data.lod_distance__morph_start__skirt_depth__lod_count.y;
+    let lod_count = terrain_data.lod_distance__morph_start__skirt_depth__lod_count.w;
+
+    if lod + 1.0 >= lod_count {
+        return grid;
+    }
+
+    let stride = exp2(lod);
+    let end = lod_distance * stride;
+    let start = end * morph_start;
+    let d = distance(grid + instance.tile_offset, camera.view_pos.xz);
+    let morph = clamp((d - start) / (end - start), 0.0, 1.0);
+
+    // The far edge is in every level, even when it isn't on the stride.
+    let last = terrain_data.tile_size__mountains__dunes__spires.x - 1.0;
+    let pinned = select(vec2(1.0), vec2(0.0), grid >= vec2(last));
+
+    let frac = fract(grid / (2.0 * stride)) * 2.0 * stride;
+    return grid - frac * morph * pinned;
+}
+
 @fragment
 fn triplanar_shaded(vs: VsOut) -> @location(0) vec4<f32> {
     // Adapted from https://bgolus.medium.com/normal-mapping-for-a-triplanar-shader-10bf39dca05a
-    let v = terrain_vertex(vs.world_position.xz, terrain_data);
-    var vs_world_normal = v.normal;
+    var vs_world_normal = baked_normal(vs);
 
     let cos_theta = max(dot(vs_world_normal, vec3(0.0, 1.0, 0.0)), 0.0);
-    let layer = select(2u, 0u, cos_theta > 0.8);
+    let biome = textureSample(blend_maps, normal_sampler, vs.tile_uv, vs.layer);
+    var surface = material_layers(vs.world_position, degrees(acos(cos_theta)), biome);
+    // Spires are bare rock from their foot up, however steep they are
+    if is_spire(vs) {
+        surface = MaterialLayers(vec2(materials.spire_layer), 0.0);
+    }
 
     var blend = abs(vs_world_normal);
     blend /= blend.x + blend.y + blend.z;
@@ -83,14 +213,15 @@ fn triplanar_shaded(vs: VsOut) -> @location(0) vec4<f32> {
     let uv_y = vs.world_position.xz * 0.1;
     let uv_z = vs.world_position.xy * 0.1;
     
-    let albedo_x = to_linear(textureSample(terrain_textures, terrain_sampler, uv_x, layer).rgb);
-    let albedo_y = to_linear(textureSample(terrain_textures, terrain_sampler, uv_y, layer).rgb);
-    let albedo_z = to_linear(textureSample(terrain_textures, terrain_sampler, uv_z, layer).rgb);
-    let albedo = albedo_x * blend.x + albedo_y * blend.y + albedo_z * blend.z;
+    let texel_x = sample_materials(uv_x, surface.layers, surface.t);
+    let texel_y = sample_materials(uv_y, surface.layers, surface.t);
+    let texel_z = sample_materials(uv_z, surface.layers, surface.t);
+    let albedo = to_linear(texel_x.rgb) * blend.x + to_linear(texel_y.rgb) * blend.y + to_linear(texel_z.rgb) * blend.z;
+    let roughness = texel_x.a * blend.x + texel_y.a * blend.y + texel_z.a * blend.z;
 
-    var tnormal_x = 2.0 * textureSample(terrain_textures, terrain_sampler, uv_x, layer + 1u).xyz - 1.0;
-    var tnormal_y = 2.0 * textureSample(terrain_textures, terrain_sampler, uv_y, layer + 1u).xyz - 1.0;
-    var tnormal_z = 2.0 * textureSample(terrain_textures, terrain_sampler, uv_z, layer + 1u).xyz - 1.0;
+    var tnormal_x = 2.0 * sample_materials(uv_x, surface.layers + 1u, surface.t).xyz - 1.0;
+    var tnormal_y = 2.0 * sample_materials(uv_y, surface.layers + 1u, surface.t).xyz - 1.0;
+    var tnormal_z = 2.0 * sample_materials(uv_z, surface.layers + 1u, surface.t).xyz - 1.0;
 
     tnormal_x = vec3(
         tnormal_x.xy + vs_world_normal.zy,
@@ -111,38 +242,112 @@ fn triplanar_shaded(vs: VsOut) -> @location(0) vec4<f32> {
         tnormal_z.xyz * blend.z
     );
 
-    let ambient_strength = 0.1;
-    let ambient_color = vec3(1.0) * ambient_strength;
+    // Lit by the sky from above and light bouncing off the ground from
+    // below
+    let ambient_color = mix(
+        lighting.ground_color.rgb,
+        lighting.sky_color.rgb,
+        world_normal.y * 0.5 + 0.5,
+    );
 
-    // let light_dir = normalize(light.position - vs.world_position);
-    let light_dir = normalize(vec3(1.0, 1.0, 1.0));
+    let light_dir = lighting.sun_direction.xyz;
     let view_dir = normalize(camera.view_pos.xyz - vs.world_position);
     let half_dir = normalize(view_dir + light_dir);
 
     let diffuse_strength = max(dot(world_normal, light_dir), 0.0);
-    let diffuse_color = diffuse_strength * vec3(1.0, 1.0, 1.0);
+    let diffuse_color = diffuse_strength * lighting.sun_color.rgb;
 
-    // let specular_strength = pow(max(dot(world_normal, half_dir), 0.0), 16.0);
-    let specular_strength = 0.0;
-    let specular_color = specular_strength * vec3(1.0, 1.0, 1.0);
+    // Smoother materials have smaller, brighter highlights. Fully rough ones
+    // have none
+    let shininess = exp2(10.0 * (1.0 - roughness));
+    let specular_strength = pow(max(dot(world_normal, half_dir), 0.0), shininess) * (1.0 - roughness);
+    let specular_color = specular_strength * lighting.sun_color.rgb;
 
     let result = (ambient_color + diffuse_color + specular_color) * albedo.rgb;
 
     return vec4(result, 1.0);
 }
 
+// The two materials covering the most of a point, and how far to blend
+// from the first to the second
+struct MaterialLayers {
+    layers: vec2<u32>,
+    t: f32,
+}
+
+// Paints the material rules over each other at `p`, on ground `slope`
+// degrees steep with the biome blend `biome`
+fn material_layers(p: vec3<f32>, slope: f32, biome: vec4<f32>) -> MaterialLayers {
+    let s = noise_seed(terrain_data.seed);
+
+    var layers = vec2(materials.rules[0].layer);
+    var weights = vec2(0.0);
+    // What's left showing of the rules under the ones seen so far, going
+    // from the top down
+    var uncovered = 1.0;
+    for (var i = i32(materials.rule_count) - 1; i >= 0; i--) {
+        let rule = materials.rules[i];
+        let altitude = rule.min_altitude__max_altitude__altitude_blend;
+        let slopes = rule.min_slope__max_slope__slope_blend;
+
+        // Decorrelate the noise of each rule
+        let q = p.xz * materials.noise_frequency + s.offset + f32(i) * vec2(2.31, 5.17);
+        let noise = textureSampleLevel(rule_noise, terrain_sampler, q / RULE_NOISE_PERIOD, 0.0).r;
+        let n = (noise * 2.0 - 1.0) * rule.noise * 0.5;
+        var coverage = band(p.y + n * altitude.z, altitude.x, altitude.y, altitude.z)
+            * band(slope + n * slopes.z, slopes.x, slopes.y, slopes.z)
+            * dot(rule.biome, biome);
+        if i == 0 {
+            coverage = 1.0;
+        }
+
+        let weight = coverage * uncovered;
+        uncovered *= 1.0 - coverage;
+        if rule.layer == layers.x {
+            weights.x += weight;
+        } else if rule.layer == layers.y {
+            weights.y += weight;
+        } else if weight > weights.y {
+            layers.y = rule.layer;
+            weights.y = weight;
+        }
+        if weights.y > weights.x {
+            layers = layers.yx;
+            weights = weights.yx;
+        }
+    }
+
+    return MaterialLayers(layers, weights.y / max(weights.x + weights.y, 1e-6));
+}
+
+// 1 from `lo` to `hi`, fading to 0 across a width of `blend` centred on
+// each limit
+fn band(x: f32, lo: f32, hi: f32, blend: f32) -> f32 {
+    let w = max(blend, 1e-4);
+    let rise = clamp((x - lo) / w + 0.5, 0.0, 1.0);
+    let fall = clamp((hi - x) / w + 0.5, 0.0, 1.0);
+    return smoothstep(0.0, 1.0, rise) * smoothstep(0.0, 1.0, fall);
+}
+
+// Texel of `terrain_textures` at `uv`, blended between two layers
+fn sample_materials(uv: vec2<f32>, layers: vec2<u32>, t: f32) -> vec4<f32> {
+    let a = textureSample(terrain_textures, terrain_sampler, uv, layers.x);
+    let b = textureSample(terrain_textures, terrain_sampler, uv, layers.y);
+    return mix(a, b, t);
+}
+
 @fragment
 fn debug(vs: VsOut) -> @location(0) vec4<f32> {
-    let v = terrain_vertex(vs.world_position.xz, terrain_data);
+    let normal = baked_normal(vs);
 
     // let blend = voronoi_blend(vs.world_position.xz * 0.01, 0.3);
 
     return vec4(
         // vec3(f32(voronoi_cell(vs.world_position.xz * 0.005).x > 0.25)),
         // vec3(voronoi_cell(vs.world_position.xz * 0.005).y),
-        // vec3(v.normal.x),
-        // to_srgb(v.normal),
-        v.normal * 0.5 + 0.5,
+        // vec3(normal.x),
+        // to_srgb(normal),
+        normal * 0.5 + 0.5,
         // blend.xyz,
         // vec3(blend.x),
         1,
@@ -164,139 +369,8 @@ fn to_linear(srgb: vec3<f32>) -> vec3<f32> {
 
     return select(higher, lower, cutoff);
 }
-
-fn terrain_vertex(p: vec2<f32>, data: TerrainData) -> TerrainVertex {
-    let v = terrain_point(p, data);
-
-    let tpx = terrain_point(p + vec2<f32>(0.01, 0.0), data) - v;
-    let tnx = terrain_point(p + vec2<f32>(-0.01, 0.0), data) - v;
-
-    let tpz = terrain_point(p + vec2<f32>(0.0, 0.01), data) - v;
-    let tnz = terrain_point(p + vec2<f32>(0.0, -0.01), data) - v;
-
-    let pn = normalize(cross(tpz, tpx));
-    let nn = normalize(cross(tnz, tnx));
-
-    let n = (pn + nn) * 0.5;
-
-    return TerrainVertex(v, n);
-}
-
-fn terrain_point(p: vec2<f32>, data: TerrainData) -> vec3<f32> {
-    let blend = biome_blend(p);
-
-    let y0 = mountains(p, data.tile_size__mountains__dunes__spires.y);
-    let y1 = dunes(p, 0.05, 0.01, 0.01, data.tile_size__mountains__dunes__spires.z);
-    let yf = y0 * blend.x + y1 * blend.y;
-
-    return vec3<f32>(p.x, yf, p.y);
-}
-
-fn mountains(p: vec2<f32>, max_height: f32) -> f32 {
-    return fbm(p) * max_height;
-}
-
-fn dunes(p: vec2<f32>, freq: f32, offset_freq: f32, offset_amp: f32, max_height: f32) -> f32 {
-    return smooth_voronoi(p * freq + snoise2(p * offset_freq) * offset_amp) * max_height;
-}
-
-fn fbm(p: vec2<f32>) -> f32 {
-    // TODO: add this to uniforms
-    let NUM_OCTAVES: u32 = 4u;
-    var x = p * 0.01;
-    var v = 0.0;
-    var a = 0.5;
-    let shift = vec2<f32>(100.0);
-    let cs = vec2<f32>(cos(0.5), sin(0.5));
-    let rot = mat2x2<f32>(cs.x, cs.y, -cs.y, cs.x);
-
-    for (var i = 0u; i < NUM_OCTAVES; i = i + 1u) {
-        v = v + a * snoise2(x);
-        x = rot * x * 2.0 + shift;
-        a = a * 0.5;
-    }
-
-    return v * 0.5 + 0.5;
-}
-
-fn smooth_voronoi(x: vec2<f32>) -> f32 {
-    let p = floor(x);
-    let f = fract(x);
-
-    var res = 0.0;
-    for (var j = -1; j <= 1; j++) {
-        for (var i = -1; i <= 1; i++) {
-            let b = vec2(f32(i), f32(j));
-            let r = vec2(b) - f + hash(p + b);
-            let d = dot(r, r);
-
-            res += 1.0 / pow(d, 8.0);
-        }
-    }
-
-    return pow(1.0 / res, 1.0 / 16.0);
-}
-
-fn biome_blend(p: vec2<f32>) -> vec4<f32> {
-    let r = 200.0;
-    let c = vec2(terrain_data.tile_size__mountains__dunes__spires.x * 16.0);
-
-    let d = length(p - c) - r;
-
-    var f = d / r * 0.5 + 0.5;
-    f = cos(f * 3.14159) * 0.5 + 0.5;
-
-    var blend = vec4(
-        max(f, 0.0),
-        max(1.0 - f, 0.0),
-        0.0,
-        0.0,
-    );
-
-    let sum = blend.x + blend.y + blend.z + blend.w;
-
-    blend *= 1.0 / sum;
-
-    return blend;
-}
-
-fn voronoi_blend(p: vec2<f32>, cutoff: f32) -> vec4<f32> {
-    let g = floor(p);
-
-    var blend = vec4(0.0);
-    var _d = 10.0;
-
-    for (var j = -1; j <= 1; j++) {
-        for (var i = -1; i < 1; i++) {
-            let b = g + vec2(f32(i), f32(j));
-            let q = b + hash(b);
-            let index = u32(2.0 * hash(b));
-            let r = (p - q);
-            let d = dot(r, r);
-
-            var col = vec4(0.0);
-            col[index] = 1.0;
-
-            
-            let h = smoothstep(-1.0, 1.0, (_d-d)/cutoff);
-            _d = mix(_d, d, h) - h*(1.0-h)*cutoff/(1.0+3.0*cutoff);
-            blend = mix(blend, col, h) - h*(1.0-h)*cutoff/(1.0+3.0*cutoff);
-        }
-    }
-
-    return normalize(blend);
-}
-
-fn offset(p: vec2<f32>) -> vec2<f32> {
-    return vec2(hash(p.xx), hash(p.yy));
-}
-
-fn hash(p: vec2<f32>) -> f32 {
-    return fract(sin(dot(p.xy, vec2(12.9898,78.233))) * 43758.5453123);
-}
-
 fn random_color(p: vec2<f32>) -> vec3<f32> {
-    let hue = hash(p) * 360.0;
+    let hue = hash(p, noise_seed(terrain_data.seed)) * 360.0;
     let saturation = 1.0;
     let lightness = 0.5;
 
@@ -327,29 +401,3 @@ fn hsl_to_rgb(h: f32, s: f32, l: f32) -> vec3<f32> {
     
     return rgb + vec3<f32>(m);
 }
-
-// https://gist.github.com/munrocket/236ed5ba7e409b8bdf1ff6eca5dcdc39
-//  MIT License. © Ian McEwan, Stefan Gustavson, Munrocket
-// - Less condensed glsl implementation with comments can be found at https://weber.itn.liu.se/~stegu/jgt2012/article.pdf
-
-fn permute3(x: vec3<f32>) -> vec3<f32> { return (((x * 34.) + 1.) * x) % vec3<f32>(289.); }
-
-fn snoise2(v: vec2<f32>) -> f32 {
-    let C = vec4<f32>(0.211324865405187, 0.366025403784439, -0.577350269189626, 0.024390243902439);
-    var i: vec2<f32> = floor(v + dot(v, C.yy));
-    let x0 = v - i + dot(i, C.xx);
-    var i1: vec2<f32> = select(vec2<f32>(0., 1.), vec2<f32>(1., 0.), (x0.x > x0.y));
-    var x12: vec4<f32> = x0.xyxy + C.xxzz - vec4<f32>(i1, 0., 0.);
-    i = i % vec2<f32>(289.);
-    let p = permute3(permute3(i.y + vec3<f32>(0., i1.y, 1.)) + i.x + vec3<f32>(0., i1.x, 1.));
-    var m: vec3<f32> = max(0.5 - vec3<f32>(dot(x0, x0), dot(x12.xy, x12.xy), dot(x12.zw, x12.zw)), vec3<f32>(0.));
-    m = m * m;
-    m = m * m;
-    let x = 2. * fract(p * C.www) - 1.;
-    let h = abs(x) - 0.5;
-    let ox = floor(x + 0.5);
-    let a0 = x - ox;
-    m = m * (1.79284291400159 - 0.85373472095314 * (a0 * a0 + h * h));
-    let g = vec3<f32>(a0.x * x0.x + h.x * x0.y, a0.yz * x12.xz + h.yz * x12.yw);
-    return 130. * dot(m, g);
-}
\ No newline at end of file
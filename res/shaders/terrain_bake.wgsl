@@ -0,0 +1,138 @@
+// Bakes the height and normal of every vertex of a tile into a layer of the
+// height and normal map arrays, so drawing doesn't have to evaluate the
+// noise every frame. The normal map's alpha holds how much spire rock
+// covers the vertex, and the blend maps hold its biome blend.
+
+@group(0)
+@binding(1)
+var height_maps: texture_storage_2d_array<r32float, write>;
+@group(0)
+@binding(2)
+var normal_maps: texture_storage_2d_array<rgba16float, write>;
+@group(0)
+@binding(6)
+var blend_maps: texture_storage_2d_array<rgba8unorm, write>;
+
+// Matches `HeightMode` in `src/game/world/terrain`, with 0 for tiles that
+// have no height samples.
+const HEIGHTS_NONE: u32 = 0u;
+const HEIGHTS_OFFSET: u32 = 1u;
+const HEIGHTS_REPLACE: u32 = 2u;
+
+struct BakeJob {
+    tile_offset: vec2<f32>,
+    layer: u32,
+    height_mode: u32,
+    // Index of the tile's first sample in `tile_heights`
+    heights_offset: u32,
+}
+
+@group(0)
+@binding(3)
+var<storage, read> jobs: array<BakeJob>;
+@group(0)
+@binding(4)
+var<storage, read> tile_heights: array<f32>;
+// Biome weights of each tile, for `biome_blend`.
+@group(0)
+@binding(5)
+var biome_map: texture_2d<f32>;
+
+struct TerrainVertex {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+}
+
+@compute
+@workgroup_size(8, 8, 1)
+fn bake_tile(@builtin(global_invocation_id) id: vec3<u32>) {
+    let num_vertices = u32(terrain_data.tile_size__mountains__dunes__spires.x);
+    if id.x >= num_vertices || id.y >= num_vertices {
+        return;
+    }
+
+    let job = jobs[id.z];
+    let p = job.tile_offset + vec2<f32>(id.xy);
+    let v = tile_vertex(p, job);
+    let blend = biome_blend(p);
+    let rock = spire_rock(p, blend, v.position.y);
+
+    textureStore(height_maps, id.xy, job.layer, vec4(v.position.y, 0.0, 0.0, 0.0));
+    textureStore(normal_maps, id.xy, job.layer, vec4(normalize(v.normal), rock));
+    textureStore(blend_maps, id.xy, job.layer, blend);
+}
+
+// How much of `p` is covered by spire rock, given its biome blend and the
+// height it's baked at. Spires that were sculpted away, replaced or worn
+// down by the simulations lose their rock with their height.
+fn spire_rock(p: vec2<f32>, blend: vec4<f32>, height: f32) -> f32 {
+    let s = noise_seed(terrain_data.seed);
+    let spire = spires(p, terrain_data, s) * blend.z;
+    if spire <= 0.0 {
+        return 0.0;
+    }
+
+    // The spires' share of the height, as in `terrain_point`
+    let floor = mountains(p, terrain_data, s) * blend.x
+        + dunes(p, terrain_data, s) * (blend.y + blend.z);
+    let standing = clamp((height - floor) / spire, 0.0, 1.0);
+    let max_height = terrain_data.tile_size__mountains__dunes__spires.w;
+    return spire / max_height * standing;
+}
+
+fn tile_vertex(p: vec2<f32>, job: BakeJob) -> TerrainVertex {
+    let v = tile_point(p, job);
+
+    let tpx = tile_point(p + vec2<f32>(0.01, 0.0), job) - v;
+    let tnx = tile_point(p + vec2<f32>(-0.01, 0.0), job) - v;
+
+    let tpz = tile_point(p + vec2<f32>(0.0, 0.01), job) - v;
+    let tnz = tile_point(p + vec2<f32>(0.0, -0.01), job) - v;
+
+    let pn = normalize(cross(tpz, tpx));
+    let nn = normalize(cross(tnz, tnx));
+
+    let n = (pn + nn) * 0.5;
+
+    return TerrainVertex(v, n);
+}
+
+fn tile_point(p: vec2<f32>, job: BakeJob) -> vec3<f32> {
+    var v = terrain_point(p, terrain_data);
+
+    switch job.height_mode {
+        case HEIGHTS_OFFSET: {
+            v.y += tile_height(p - job.tile_offset, job);
+        }
+        case HEIGHTS_REPLACE: {
+            v.y = tile_height(p - job.tile_offset, job);
+        }
+        default: {}
+    }
+
+    return v;
+}
+
+// Bilinearly samples the tile's heights, clamping to its edges. Mirrors
+// `TileHeights::sample`.
+fn tile_height(local: vec2<f32>, job: BakeJob) -> f32 {
+    let n = u32(terrain_data.tile_size__mountains__dunes__spires.x);
+    let max_p = f32(n - 1u);
+    let p = clamp(local, vec2(0.0), vec2(max_p));
+    let p0 = max(min(floor(p), vec2(max_p - 1.0)), vec2(0.0));
+    let f = p - p0;
+
+    let x = u32(p0.x);
+    let z = u32(p0.y);
+    let x1 = min(x + 1u, n - 1u);
+    let z1 = min(z + 1u, n - 1u);
+
+    let h00 = tile_heights[job.heights_offset + x + z * n];
+    let h10 = tile_heights[job.heights_offset + x1 + z * n];
+    let h01 = tile_heights[job.heights_offset + x + z1 * n];
+    let h11 = tile_heights[job.heights_offset + x1 + z1 * n];
+
+    let h0 = h00 + (h10 - h00) * f.x;
+    let h1 = h01 + (h11 - h01) * f.x;
+    return h0 + (h1 - h0) * f.y;
+}
@@ -0,0 +1,262 @@
+// Shared by `terrain.wgsl` and `terrain_bake.wgsl`, which are each compiled
+// with this file prepended.
+
+struct TerrainData {
+    tile_size__mountains__dunes__spires: vec4<f32>,
+    lod_distance__morph_start__skirt_depth__lod_count: vec4<f32>,
+    mountain_octaves__frequency__lacunarity__gain: vec4<f32>,
+    mountain_rotation__dune_frequency__warp_frequency__warp_amplitude: vec4<f32>,
+    spire_frequency__radius__terraces__roughness: vec4<f32>,
+    spire_coverage__mask_frequency: vec2<f32>,
+    seed: u32,
+}
+
+@group(0)
+@binding(0)
+var<uniform> terrain_data: TerrainData;
+
+// The terrain functions below are mirrored on the CPU in
+// `src/game/world/terrain`. Keep the two in sync.
+fn terrain_point(p: vec2<f32>, data: TerrainData) -> vec3<f32> {
+    let blend = biome_blend(p);
+    let s = noise_seed(data.seed);
+
+    let y0 = mountains(p, data, s);
+    let y1 = dunes(p, data, s);
+    let y2 = spires(p, data, s);
+    // Spires stand on dunes, so the desert floor between them is the same
+    let yf = y0 * blend.x + y1 * (blend.y + blend.z) + y2 * blend.z;
+
+    return vec3<f32>(p.x, yf, p.y);
+}
+
+fn mountains(p: vec2<f32>, data: TerrainData, s: NoiseSeed) -> f32 {
+    let fbm_params = data.mountain_octaves__frequency__lacunarity__gain;
+    let rotation = data.mountain_rotation__dune_frequency__warp_frequency__warp_amplitude.x;
+    let max_height = data.tile_size__mountains__dunes__spires.y;
+    return fbm(p, u32(fbm_params.x), fbm_params.y, fbm_params.z, fbm_params.w, rotation, s) * max_height;
+}
+
+fn dunes(p: vec2<f32>, data: TerrainData, s: NoiseSeed) -> f32 {
+    let params = data.mountain_rotation__dune_frequency__warp_frequency__warp_amplitude;
+    let max_height = data.tile_size__mountains__dunes__spires.z;
+    let warp = snoise2(p * params.z, s) * params.w;
+    return smooth_voronoi(p * params.y + warp, s) * max_height;
+}
+
+fn spires(p: vec2<f32>, data: TerrainData, s: NoiseSeed) -> f32 {
+    let params = data.spire_frequency__radius__terraces__roughness;
+    let max_height = data.tile_size__mountains__dunes__spires.w;
+    return spire_columns(p, params.x, params.y, params.z, params.w, s) * max_height;
+}
+
+// How much of the desert at `p` is covered by spires, from 0 to 1.
+fn spire_mask(p: vec2<f32>, data: TerrainData, s: NoiseSeed) -> f32 {
+    let params = data.spire_coverage__mask_frequency;
+    let n = snoise2(p * params.y, s) * 0.5 + 0.5;
+    return smoothstep(0.95 - params.x, 1.05 - params.x, n);
+}
+
+// Columns of rock, at most one per cell of a jittered grid, from 0 on the
+// ground to 1 at the top of the tallest. Their sides are roughened and
+// stepped into ledges, like rock eroded in layers.
+fn spire_columns(
+    p: vec2<f32>,
+    frequency: f32,
+    radius: f32,
+    terraces: f32,
+    roughness: f32,
+    s: NoiseSeed,
+) -> f32 {
+    let x = p * frequency;
+    let g = floor(x);
+    let f = fract(x);
+    let rough = snoise2(x * 8.0, s) * roughness;
+
+    var h = 0.0;
+    for (var j = -1; j <= 1; j++) {
+        for (var i = -1; i <= 1; i++) {
+            let b = vec2(f32(i), f32(j));
+            let cell = g + b;
+            let center = b + 0.25 + 0.5 * vec2(hash(cell, s), hash(cell + 17.0, s));
+            let size = radius * (0.5 + 0.5 * hash(cell + 31.0, s));
+            let top = 0.4 + 0.6 * hash(cell + 53.0, s);
+            let d = length(center - f) + rough;
+
+            h = max(h, (1.0 - smoothstep(size * 0.7, size, d)) * top);
+        }
+    }
+
+    let steps = max(terraces, 1.0);
+    let t = h * steps;
+    return (floor(t) + smoothstep(0.6, 1.0, fract(t))) / steps;
+}
+
+// What a terrain's seed changes about the noise. Seed 0 leaves the noise as
+// it was before terrains had seeds.
+struct NoiseSeed {
+    // Added to the simplex lattice before it is permuted.
+    offset: vec2<f32>,
+    // Added to the argument of `sin` in `hash`.
+    phase: f32,
+}
+
+fn noise_seed(seed: u32) -> NoiseSeed {
+    if seed == 0u {
+        return NoiseSeed(vec2(0.0), 0.0);
+    }
+
+    let h0 = pcg(seed);
+    let h1 = pcg(h0);
+    let h2 = pcg(h1);
+    return NoiseSeed(
+        vec2(f32(h0 % 289u), f32(h1 % 289u)),
+        f32(h2 >> 8u) / 16777216.0 * 6.2831855,
+    );
+}
+
+// https://www.jcgt.org/published/0009/03/02/
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn fbm(
+    p: vec2<f32>,
+    octaves: u32,
+    frequency: f32,
+    lacunarity: f32,
+    gain: f32,
+    rotation: f32,
+    s: NoiseSeed,
+) -> f32 {
+    var x = p * frequency;
+    var v = 0.0;
+    var a = 0.5;
+    let shift = vec2<f32>(100.0);
+    let cs = vec2<f32>(cos(rotation), sin(rotation));
+    let rot = mat2x2<f32>(cs.x, cs.y, -cs.y, cs.x);
+
+    for (var i = 0u; i < octaves; i = i + 1u) {
+        v = v + a * snoise2(x, s);
+        x = rot * x * lacunarity + shift;
+        a = a * gain;
+    }
+
+    return v * 0.5 + 0.5;
+}
+
+fn smooth_voronoi(x: vec2<f32>, s: NoiseSeed) -> f32 {
+    let p = floor(x);
+    let f = fract(x);
+
+    var res = 0.0;
+    for (var j = -1; j <= 1; j++) {
+        for (var i = -1; i <= 1; i++) {
+            let b = vec2(f32(i), f32(j));
+            let r = vec2(b) - f + hash(p + b, s);
+            let d = dot(r, r);
+
+            res += 1.0 / pow(d, 8.0);
+        }
+    }
+
+    return pow(1.0 / res, 1.0 / 16.0);
+}
+
+// Weights of mountains, dunes and spires at `p`, adding up to one. Spires
+// only stand on part of the spire biome, the rest is dunes.
+fn biome_blend(p: vec2<f32>) -> vec4<f32> {
+    let w = biome_weights(p);
+    let spires = w.z * spire_mask(p, terrain_data, noise_seed(terrain_data.seed));
+
+    var blend = vec4(w.x, w.y + w.z - spires, spires, 0.0);
+
+    let sum = blend.x + blend.y + blend.z + blend.w;
+
+    blend *= 1.0 / sum;
+
+    return blend;
+}
+
+// Interpolates the biome weights of the four tiles whose centres are
+// nearest `p`. `biome_map` has a texel per tile, and is declared by each
+// shader that includes this file.
+fn biome_weights(p: vec2<f32>) -> vec3<f32> {
+    let t = p / (terrain_data.tile_size__mountains__dunes__spires.x - 1.0) - 0.5;
+    let t0 = floor(t);
+    let f = t - t0;
+
+    let last = vec2<i32>(textureDimensions(biome_map)) - 1;
+    let i0 = clamp(vec2<i32>(t0), vec2(0), last);
+    let i1 = clamp(vec2<i32>(t0) + 1, vec2(0), last);
+    let w00 = textureLoad(biome_map, i0, 0).xyz;
+    let w10 = textureLoad(biome_map, vec2(i1.x, i0.y), 0).xyz;
+    let w01 = textureLoad(biome_map, vec2(i0.x, i1.y), 0).xyz;
+    let w11 = textureLoad(biome_map, i1, 0).xyz;
+
+    let w0 = w00 + (w10 - w00) * f.x;
+    let w1 = w01 + (w11 - w01) * f.x;
+    return w0 + (w1 - w0) * f.y;
+}
+
+fn voronoi_blend(p: vec2<f32>, cutoff: f32, s: NoiseSeed) -> vec4<f32> {
+    let g = floor(p);
+
+    var blend = vec4(0.0);
+    var _d = 10.0;
+
+    for (var j = -1; j <= 1; j++) {
+        for (var i = -1; i < 1; i++) {
+            let b = g + vec2(f32(i), f32(j));
+            let q = b + hash(b, s);
+            let index = u32(2.0 * hash(b, s));
+            let r = (p - q);
+            let d = dot(r, r);
+
+            var col = vec4(0.0);
+            col[index] = 1.0;
+
+            
+            let h = smoothstep(-1.0, 1.0, (_d-d)/cutoff);
+            _d = mix(_d, d, h) - h*(1.0-h)*cutoff/(1.0+3.0*cutoff);
+            blend = mix(blend, col, h) - h*(1.0-h)*cutoff/(1.0+3.0*cutoff);
+        }
+    }
+
+    return normalize(blend);
+}
+
+fn offset(p: vec2<f32>, s: NoiseSeed) -> vec2<f32> {
+    return vec2(hash(p.xx, s), hash(p.yy, s));
+}
+
+fn hash(p: vec2<f32>, s: NoiseSeed) -> f32 {
+    return fract(sin(dot(p.xy, vec2(12.9898,78.233)) + s.phase) * 43758.5453123);
+}
+// https://gist.github.com/munrocket/236ed5ba7e409b8bdf1ff6eca5dcdc39
+//  MIT License. © Ian McEwan, Stefan Gustavson, Munrocket
+// - Less condensed glsl implementation with comments can be found at https://weber.itn.liu.se/~stegu/jgt2012/article.pdf
+
+fn permute3(x: vec3<f32>) -> vec3<f32> { return (((x * 34.) + 1.) * x) % vec3<f32>(289.); }
+
+fn snoise2(v: vec2<f32>, s: NoiseSeed) -> f32 {
+    let C = vec4<f32>(0.211324865405187, 0.366025403784439, -0.577350269189626, 0.024390243902439);
+    var i: vec2<f32> = floor(v + dot(v, C.yy));
+    let x0 = v - i + dot(i, C.xx);
+    var i1: vec2<f32> = select(vec2<f32>(0., 1.), vec2<f32>(1., 0.), (x0.x > x0.y));
+    var x12: vec4<f32> = x0.xyxy + C.xxzz - vec4<f32>(i1, 0., 0.);
+    i = (i + s.offset) % vec2<f32>(289.);
+    let p = permute3(permute3(i.y + vec3<f32>(0., i1.y, 1.)) + i.x + vec3<f32>(0., i1.x, 1.));
+    var m: vec3<f32> = max(0.5 - vec3<f32>(dot(x0, x0), dot(x12.xy, x12.xy), dot(x12.zw, x12.zw)), vec3<f32>(0.));
+    m = m * m;
+    m = m * m;
+    let x = 2. * fract(p * C.www) - 1.;
+    let h = abs(x) - 0.5;
+    let ox = floor(x + 0.5);
+    let a0 = x - ox;
+    m = m * (1.79284291400159 - 0.85373472095314 * (a0 * a0 + h * h));
+    let g = vec3<f32>(a0.x * x0.x + h.x * x0.y, a0.yz * x12.xz + h.yz * x12.yw);
+    return 130. * dot(m, g);
+}
\ No newline at end of file
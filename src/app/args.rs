@@ -0,0 +1,260 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::game::world::terrain::{
+    export::ExportLayout,
+    import::{HeightmapFit, HeightmapOptions},
+    mesh::MeshOptions,
+};
+
+pub const USAGE: &str = "\
+Usage: dirt [options]
+
+Options:
+  --seed <seed>              Generate a new terrain from this seed instead of loading the saved one
+  --import-heightmap <file>  Replace the terrain with a PNG or TIFF heightmap
+  --height-scale <height>    Height of a white heightmap pixel (default 100)
+  --sea-level <height>       Heightmap height that becomes zero (default 0)
+  --resample                 Stretch heightmaps to fit the tiles instead of padding them
+  --export-heightmap <dir>   Export heightmaps and normal maps of the terrain on startup
+  --export-per-tile          Export one image per tile instead of one for the terrain
+  --export-mesh <file>       Export the terrain as an .obj, .glb or .stl mesh on startup
+  --mesh-tiles <x,z,x,z>     First and last tile to export as a mesh (default all)
+  --mesh-decimation <level>  Level of detail to export the mesh at (default 0)
+  --mesh-base <depth>        Depth of an STL's base below the terrain (default 5)
+  --help                     Show this message";
+
+/// What the command line asks for.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Run(Args),
+    /// `--help` was passed, so print [`USAGE`] instead of running.
+    Help,
+}
+
+/// Options passed on the command line.
+#[derive(Debug, Default, Clone)]
+pub struct Args {
+    pub seed: Option<u32>,
+    pub import_heightmap: Option<PathBuf>,
+    pub heightmap_options: HeightmapOptions,
+    pub export_heightmap: Option<PathBuf>,
+    pub export_layout: ExportLayout,
+    pub export_mesh: Option<PathBuf>,
+    pub mesh_options: MeshOptions,
+}
+
+impl Command {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .with_context(|| format!("{arg} needs a value\n\n{USAGE}"))
+            };
+            match arg.as_str() {
+                "--seed" => parsed.seed = Some(parse_value(&arg, &value()?)?),
+                "--import-heightmap" => {
+                    // Relative to where dirt was run from, not the res folder
+                    parsed.import_heightmap = Some(std::env::current_dir()?.join(value()?));
+                }
+                "--height-scale" => {
+                    parsed.heightmap_options.vertical_scale = parse_value(&arg, &value()?)?;
+                }
+                "--sea-level" => {
+                    parsed.heightmap_options.sea_level = parse_value(&arg, &value()?)?;
+                }
+                "--resample" => parsed.heightmap_options.fit = HeightmapFit::Resample,
+                "--export-heightmap" => {
+                    parsed.export_heightmap = Some(std::env::current_dir()?.join(value()?));
+                }
+                "--export-per-tile" => parsed.export_layout = ExportLayout::PerTile,
+                "--export-mesh" => {
+                    parsed.export_mesh = Some(std::env::current_dir()?.join(value()?));
+                }
+                "--mesh-tiles" => {
+                    let value = value()?;
+                    let tiles = value
+                        .split(',')
+                        .map(|v| parse_value(&arg, v))
+                        .collect::<anyhow::Result<Vec<u32>>>()?;
+                    let &[x0, z0, x1, z1] = tiles.as_slice() else {
+                        anyhow::bail!("{arg} needs four tile coordinates, not {value}");
+                    };
+                    parsed.mesh_options.tiles = Some(((x0, z0), (x1, z1)));
+                }
+                "--mesh-decimation" => {
+                    parsed.mesh_options.decimation = parse_value(&arg, &value()?)?;
+                }
+                "--mesh-base" => {
+                    parsed.mesh_options.base_depth = parse_value(&arg, &value()?)?;
+                }
+                "--help" => return Ok(Self::Help),
+                _ => anyhow::bail!("Unknown option {arg}\n\n{USAGE}"),
+            }
+        }
+
+        Ok(Self::Run(parsed))
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    value
+        .parse()
+        .with_context(|| format!("Invalid value for {arg}: {value}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Args> {
+        match Command::parse(args.iter().map(|arg| arg.to_string()))? {
+            Command::Run(args) => Ok(args),
+            Command::Help => anyhow::bail!("asked for help"),
+        }
+    }
+
+    fn error(args: &[&str]) -> String {
+        format!("{:#}", parse(args).unwrap_err())
+    }
+
+    #[test]
+    fn parses_nothing_as_defaults() {
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.seed, None);
+        assert_eq!(args.import_heightmap, None);
+        assert_eq!(args.export_layout, ExportLayout::Single);
+    }
+
+    #[test]
+    fn parses_terrain_flags() {
+        let args = parse(&[
+            "--seed",
+            "42",
+            "--import-heightmap",
+            "map.png",
+            "--height-scale",
+            "250.5",
+            "--sea-level",
+            "-3",
+            "--resample",
+        ])
+        .unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(args.seed, Some(42));
+        assert_eq!(args.import_heightmap, Some(cwd.join("map.png")));
+        assert_eq!(args.heightmap_options.vertical_scale, 250.5);
+        assert_eq!(args.heightmap_options.sea_level, -3.0);
+        assert_eq!(args.heightmap_options.fit, HeightmapFit::Resample);
+    }
+
+    #[test]
+    fn parses_export_flags() {
+        let args = parse(&[
+            "--export-heightmap",
+            "out",
+            "--export-per-tile",
+            "--export-mesh",
+            "terrain.obj",
+        ])
+        .unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(args.export_heightmap, Some(cwd.join("out")));
+        assert_eq!(args.export_layout, ExportLayout::PerTile);
+        assert_eq!(args.export_mesh, Some(cwd.join("terrain.obj")));
+    }
+
+    #[test]
+    fn parses_mesh_flags() {
+        let args = parse(&[
+            "--mesh-tiles",
+            "1,2,3,4",
+            "--mesh-decimation",
+            "2",
+            "--mesh-base",
+            "0.5",
+        ])
+        .unwrap();
+        assert_eq!(args.mesh_options.tiles, Some(((1, 2), (3, 4))));
+        assert_eq!(args.mesh_options.decimation, 2);
+        assert_eq!(args.mesh_options.base_depth, 0.5);
+
+        let args = parse(&[]).unwrap();
+        assert_eq!(args.mesh_options.tiles, None);
+    }
+
+    #[test]
+    fn rejects_malformed_mesh_flags() {
+        for tiles in ["1,2,3", "1,2,3,4,5", ""] {
+            let error = error(&["--mesh-tiles", tiles]);
+            assert!(error.contains("--mesh-tiles"), "{error}");
+        }
+        for (tiles, invalid) in [("1,2,x,4", "x"), ("0,0,-1,1", "-1"), ("1, 2,3,4", " 2")] {
+            let error = error(&["--mesh-tiles", tiles]);
+            let expected = format!("Invalid value for --mesh-tiles: {invalid}");
+            assert!(error.contains(&expected), "{error}");
+        }
+        for flag in ["--mesh-tiles", "--mesh-decimation", "--mesh-base"] {
+            let error = error(&[flag]);
+            assert!(error.contains(&format!("{flag} needs a value")), "{error}");
+        }
+        let error = error(&["--mesh-decimation", "half"]);
+        assert!(
+            error.contains("Invalid value for --mesh-decimation: half"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn help_is_left_to_the_caller() {
+        let help = Command::parse(["--seed", "1", "--help"].map(String::from)).unwrap();
+        assert!(matches!(help, Command::Help));
+    }
+
+    #[test]
+    fn rejects_missing_values() {
+        for flag in [
+            "--seed",
+            "--import-heightmap",
+            "--height-scale",
+            "--sea-level",
+            "--export-heightmap",
+            "--export-mesh",
+        ] {
+            let error = error(&[flag]);
+            assert!(error.contains(&format!("{flag} needs a value")), "{error}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for args in [
+            ["--seed", "-1"],
+            ["--seed", "ten"],
+            ["--height-scale", "tall"],
+            ["--sea-level", ""],
+        ] {
+            let error = error(&args);
+            let expected = format!("Invalid value for {}: {}", args[0], args[1]);
+            assert!(error.contains(&expected), "{error}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let error = error(&["--sed", "1"]);
+        assert!(error.contains("Unknown option --sed"), "{error}");
+        assert!(error.contains(USAGE), "{error}");
+    }
+}
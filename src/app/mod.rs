@@ -15,7 +15,9 @@ use winit::{
     window::WindowAttributes,
 };
 
-use crate::game::Game;
+use crate::{app::args::Args, game::Game};
+
+pub(crate) mod args;
 
 pub enum AppEvent {
     GameStarted(Game),
@@ -66,7 +68,7 @@ pub struct App {
 }
 
 impl App {
-    pub fn new(proxy: EventLoopProxy<AppEvent>, res_dir: impl Into<PathBuf>) -> Self {
+    pub fn new(proxy: EventLoopProxy<AppEvent>, res_dir: impl Into<PathBuf>, args: Args) -> Self {
         let gamepads = gilrs::GilrsBuilder::new().build().unwrap();
         Self {
             game: None,
@@ -74,6 +76,7 @@ impl App {
             controller: AppController {
                 proxy,
                 res_dir: res_dir.into(),
+                args: Arc::new(args),
             },
         }
     }
@@ -181,9 +184,19 @@ impl ApplicationHandler<AppEvent> for App {
             AppEvent::SaveBinary(path, contents, sender) => {
                 log::debug!("SaveBinary");
                 self.spawn_task(async move {
+                    let result = async {
+                        if let Some(parent) = path.parent() {
+                            async_fs::create_dir_all(parent).await?;
+                        }
+                        async_fs::write(&path, &contents).await
+                    };
                     sender
-                        .send(async_fs::write(&path, &contents).await.with_context(|| {
-                            format!("Could not save data: {:?} to {}", &contents, path.display())
+                        .send(result.await.with_context(|| {
+                            format!(
+                                "Could not save {} bytes to {}",
+                                contents.len(),
+                                path.display()
+                            )
                         }))
                         .await
                         .unwrap();
@@ -224,6 +237,10 @@ impl ApplicationHandler<AppEvent> for App {
             WindowEvent::MouseInput { state, button, .. } => {
                 game.handle_mouse_button(button, state.is_pressed())
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                game.handle_cursor_moved(position.x as _, position.y as _)
+            }
+            WindowEvent::ModifiersChanged(modifiers) => game.handle_modifiers(modifiers.state()),
             WindowEvent::RedrawRequested => game.render(app),
             WindowEvent::Resized(size) => game.resize(size.width, size.height),
             _ => {}
@@ -256,9 +273,14 @@ impl ApplicationHandler<AppEvent> for App {
 pub struct AppController {
     res_dir: PathBuf,
     proxy: EventLoopProxy<AppEvent>,
+    args: Arc<Args>,
 }
 
 impl AppController {
+    pub(crate) fn args(&self) -> &Args {
+        &self.args
+    }
+
     pub fn exit(&self) {
         self.proxy.send_event(AppEvent::Exit).unwrap();
     }
@@ -281,6 +303,49 @@ impl AppController {
         receiver.recv().await?
     }
 
+    /// Saves `data` to `path`, creating any missing folders.
+    pub async fn save_binary(&self, path: impl AsRef<Path>, data: Vec<u8>) -> anyhow::Result<()> {
+        let path = self.res_dir.join(path);
+        let (sender, receiver) = bounded(1);
+        self.proxy
+            .send_event(AppEvent::SaveBinary(path, data, sender))
+            .unwrap();
+        receiver.recv().await?
+    }
+
+    /// Reports a file that could not be loaded and keeps a copy of `data`
+    /// as `<path>.bak`, so it isn't lost when the file is next saved.
+    /// `fallback` says what is used instead. Returns the message to show
+    /// the player.
+    pub(crate) async fn reject_file(
+        &self,
+        path: &str,
+        data: Vec<u8>,
+        error: &anyhow::Error,
+        fallback: &str,
+    ) -> String {
+        // Point at the line and column for files that failed to parse
+        let location = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<serde_json::Error>())
+            .filter(|e| e.line() > 0)
+            .map(|e| format!(":{}:{}", e.line(), e.column()))
+            .unwrap_or_default();
+        log::error!("Could not load {path}{location}: {error:?}");
+
+        let backup = format!("{path}.bak");
+        let kept = match self.save_binary(&backup, data).await {
+            Ok(()) => format!("the bad file was kept as {backup}"),
+            Err(e) => {
+                log::error!("Could not keep a copy of {path}: {e:?}");
+                "the bad file could not be kept".to_string()
+            }
+        };
+        log::warn!("{fallback}, {kept}");
+
+        format!("Could not load {path}{location}: {error:#}\n  {fallback}, {kept}")
+    }
+
     pub(crate) async fn load_string(&self, path: impl AsRef<Path>) -> anyhow::Result<String> {
         let path = self.res_dir.join(path);
         let (sender, receiver) = bounded(1);
@@ -1,11 +1,12 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use web_time::{Duration, Instant};
 use winit::{
     dpi::PhysicalPosition,
     event::{MouseButton, MouseScrollDelta},
-    keyboard::KeyCode,
+    keyboard::{KeyCode, ModifiersState},
     window::{Fullscreen, Window},
 };
 
@@ -13,19 +14,44 @@ use crate::{
     app::AppController,
     game::{
         render::Renderer,
-        world::{World, camera::CameraController},
+        world::{
+            TERRAIN_PATH, World,
+            camera::CameraController,
+            sun::LightingOptions,
+            terrain::{
+                NoiseParam, Terrain, TerrainHit,
+                biome::Biome,
+                erosion::{self, ErosionOptions},
+                export::{self, ExportLayout},
+                file,
+                heightfield::HeightChanges,
+                mesh::{MeshFormat, MeshOptions, TerrainMesh},
+                sand::{self, SandOptions},
+                sculpt::{BrushKind, Sculptor},
+                thermal::{self, ThermalOptions},
+            },
+        },
     },
 };
 
 mod render;
-mod world;
+pub(crate) mod world;
+
+/// How far, in tiles, the camera has to move past the edge of the tile
+/// the terrain was last streamed around before it is streamed again.
+const STREAMING_HYSTERESIS: f32 = 0.25;
+const SETTINGS_PATH: &str = "settings.json";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Settings {
+    #[serde(default)]
     debug_mode_active: bool,
+    #[serde(default)]
     fullscreen: bool,
     #[serde(default = "default_move_speed")]
     move_speed: f32,
+    #[serde(default = "default_walk_speed")]
+    walk_speed: f32,
     #[serde(default = "default_tile_size")]
     tile_size: u32,
     #[serde(default = "default_terrain_height")]
@@ -34,6 +60,21 @@ struct Settings {
     terrain_size: u32,
     #[serde(default = "default_chunk_radius")]
     chunk_radius: u32,
+    #[serde(default = "default_lod_distance")]
+    lod_distance: f32,
+    /// Seed for newly generated terrains. A random one is used if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seed: Option<u32>,
+    #[serde(default)]
+    erosion: ErosionOptions,
+    #[serde(default)]
+    sand: SandOptions,
+    #[serde(default)]
+    thermal: ThermalOptions,
+    /// Where the sun is and how the terrain is lit. The time of day is
+    /// saved as it was on exit.
+    #[serde(default)]
+    lighting: LightingOptions,
 }
 
 impl Default for Settings {
@@ -42,14 +83,57 @@ impl Default for Settings {
             debug_mode_active: false,
             fullscreen: false,
             move_speed: default_move_speed(),
+            walk_speed: default_walk_speed(),
             tile_size: default_tile_size(),
             terrain_height: default_terrain_height(),
             terrain_size: default_terrain_size(),
             chunk_radius: default_chunk_radius(),
+            lod_distance: default_lod_distance(),
+            seed: None,
+            erosion: ErosionOptions::default(),
+            sand: SandOptions::default(),
+            thermal: ThermalOptions::default(),
+            lighting: LightingOptions::default(),
         }
     }
 }
 
+impl Settings {
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.tile_size >= 2,
+            "tile_size must be at least 2, not {}",
+            self.tile_size
+        );
+        anyhow::ensure!(self.terrain_size >= 1, "terrain_size must be at least 1");
+        for (name, value) in [
+            ("move_speed", self.move_speed),
+            ("walk_speed", self.walk_speed),
+        ] {
+            anyhow::ensure!(value > 0.0, "{name} must be positive, not {value}");
+        }
+        anyhow::ensure!(
+            self.terrain_height.is_finite(),
+            "terrain_height must be a number, not {}",
+            self.terrain_height
+        );
+        anyhow::ensure!(
+            self.lod_distance >= 0.0,
+            "lod_distance can't be negative, not {}",
+            self.lod_distance
+        );
+        self.erosion.validate().context("Invalid erosion options")?;
+        self.sand.validate().context("Invalid sand options")?;
+        self.thermal
+            .validate()
+            .context("Invalid thermal erosion options")?;
+        self.lighting
+            .validate()
+            .context("Invalid lighting options")?;
+        Ok(())
+    }
+}
+
 fn default_terrain_height() -> f32 {
     50.0
 }
@@ -58,6 +142,10 @@ fn default_move_speed() -> f32 {
     20.0
 }
 
+fn default_walk_speed() -> f32 {
+    6.0
+}
+
 fn default_tile_size() -> u32 {
     256
 }
@@ -70,26 +158,69 @@ fn default_chunk_radius() -> u32 {
     4
 }
 
+/// In tiles
+fn default_lod_distance() -> f32 {
+    2.0
+}
+
 pub struct Game {
     renderer: Renderer,
     world: World,
     pub(crate) window: Arc<Window>,
     settings: Settings,
     terrain_id: usize,
+    streaming_center: Option<glam::IVec2>,
     camera_controller: CameraController,
     game_play_timer: Instant,
     frame_timer: Instant,
     lmb_pressed: bool,
+    rmb_pressed: bool,
+    modifiers: ModifiersState,
+    /// Last position of the cursor in the window, in pixels.
+    cursor: Option<glam::Vec2>,
+    /// Terrain under the cursor, updated every frame.
+    hovered: Option<TerrainHit>,
+    sculpt_mode: bool,
+    sculptor: Sculptor,
+    /// Receives the result of the simulation running in the background,
+    /// if there is one.
+    simulation: Option<async_channel::Receiver<HeightChanges>>,
+    /// Simulations started so far, which goes into their seeds.
+    simulation_runs: u32,
+    /// Noise parameter the debug keys change.
+    noise_param: NoiseParam,
     num_frames: i32,
     tick_rate: Duration,
     debug_text: usize,
+    /// Why files couldn't be loaded, shown under the debug text.
+    load_errors: Vec<String>,
     render_time: Duration,
 }
 
 impl Game {
     pub async fn new(app: &AppController, window: Arc<Window>) -> anyhow::Result<Self> {
-        let settings = match app.load_string("settings.json").await {
-            Ok(json) => serde_json::from_str(&json)?,
+        let mut load_errors = Vec::new();
+        let settings = match app.load_string(SETTINGS_PATH).await {
+            Ok(json) => {
+                let settings = serde_json::from_str::<Settings>(&json)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|settings| settings.validate().map(|()| settings));
+                match settings {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        load_errors.push(
+                            app.reject_file(
+                                SETTINGS_PATH,
+                                json.into_bytes(),
+                                &e,
+                                "Using the default settings",
+                            )
+                            .await,
+                        );
+                        Settings::default()
+                    }
+                }
+            }
             Err(_) => Settings::default(),
         };
 
@@ -99,22 +230,25 @@ impl Game {
 
         log::debug!("Creating Renderer");
         let mut renderer = Renderer::new(app, window.clone()).await?;
+        load_errors.append(&mut renderer.load_errors);
 
         let width = window.inner_size().width.max(1);
         let height = window.inner_size().height.max(1);
 
-        let world = World::new(
+        let mut world = World::new(
             app,
             width,
             height,
             settings.terrain_size,
             settings.tile_size,
             settings.terrain_height,
+            settings.seed,
         )
         .await;
+        load_errors.append(&mut world.load_errors);
 
         let debug_text = renderer.buffer_text(&format!(
-            "Debug Mode: {}\nTick Rate: --\n({:.2}, {:.2}, {:.2})\n({:.2}, {:.2})",
+            "Debug Mode: {}\nTick Rate: --\n({:.2}, {:.2}, {:.2})\n({:.2}, {:.2})\nMovement: Fly\nTiles: --\nSculpt: off\nHover: --\nNoise: --\nTime: --",
             if settings.debug_mode_active {
                 "ON"
             } else {
@@ -127,27 +261,52 @@ impl Game {
             world.player_camera.pitch,
         ));
 
-        let terrain_id = renderer.buffer_terrain(&world.terrain);
-
-        renderer.update_terrain(terrain_id, &world.terrain, settings.chunk_radius);
+        let terrain_id = renderer.buffer_terrain(
+            &world.terrain,
+            settings.lod_distance * world.terrain.tile_span(),
+            settings.chunk_radius,
+        );
 
-        let camera_controller = CameraController::new(settings.move_speed, 1.0);
+        let camera_controller =
+            CameraController::new(settings.move_speed, settings.walk_speed, 1.0);
 
-        Ok(Self {
+        let mut game = Self {
             renderer,
             window,
             world,
             terrain_id,
+            streaming_center: None,
             camera_controller,
             game_play_timer: Instant::now(),
             frame_timer: Instant::now(),
             num_frames: 0,
             lmb_pressed: false,
+            rmb_pressed: false,
+            modifiers: ModifiersState::empty(),
+            cursor: None,
+            hovered: None,
+            sculpt_mode: false,
+            sculptor: Sculptor::default(),
+            simulation: None,
+            simulation_runs: 0,
+            noise_param: NoiseParam::MountainOctaves,
             tick_rate: Duration::ZERO,
             settings,
             debug_text,
+            load_errors,
             render_time: Duration::ZERO,
-        })
+        };
+
+        game.update_streaming();
+
+        if let Some(dir) = &app.args().export_heightmap {
+            game.export_heightmaps(app, dir.clone(), app.args().export_layout);
+        }
+        if let Some(path) = &app.args().export_mesh {
+            game.export_mesh(app, path.clone(), app.args().mesh_options);
+        }
+
+        Ok(game)
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -170,45 +329,105 @@ impl Game {
             self.frame_timer = Instant::now();
         }
 
-        self.camera_controller
-            .update_camera(&mut self.world.player_camera, dt);
+        self.camera_controller.update_camera(
+            &mut self.world.player_camera,
+            &self.world.terrain,
+            dt,
+        );
 
-        // self.renderer
-        //     .update_terrain(self.terrain_id, &self.world.terrain);
+        let size = self.window.inner_size();
+        self.hovered = self.cursor.and_then(|cursor| {
+            self.world
+                .pick(cursor, glam::vec2(size.width as f32, size.height as f32))
+        });
 
-        self.renderer.update_text(
-            self.debug_text,
-            &format!(
-                "Debug Mode: {}\nTick Rate: {:?}\n({:.2}, {:.2}, {:.2})\n({:.2}, {:.2})",
-                if self.settings.debug_mode_active {
-                    "ON"
-                } else {
-                    "OFF"
-                },
-                self.tick_rate,
-                self.world.player_camera.position.x,
-                self.world.player_camera.position.y,
-                self.world.player_camera.position.z,
-                self.world.player_camera.yaw,
-                self.world.player_camera.pitch,
-            ),
+        if self.sculpt_mode && self.lmb_pressed {
+            self.sculpt(dt.as_secs_f32());
+        }
+        self.finish_simulation();
+        self.settings.lighting.advance(dt.as_secs_f32());
+
+        self.update_streaming();
+        self.renderer
+            .cull_terrain(self.terrain_id, &self.world.player_camera);
+        let terrain_stats = self.renderer.terrain_stats(self.terrain_id);
+
+        let hours = self.settings.lighting.time_of_day;
+        let mut debug_text = format!(
+            "Debug Mode: {}\nTick Rate: {:?}\n({:.2}, {:.2}, {:.2})\n({:.2}, {:.2})\nMovement: {:?}\nTiles: {}/{} ({} culled)\nSculpt: {}\nHover: {}\nNoise: {} = {:.3}\nTime: {:02}:{:02}",
+            if self.settings.debug_mode_active {
+                "ON"
+            } else {
+                "OFF"
+            },
+            self.tick_rate,
+            self.world.player_camera.position.x,
+            self.world.player_camera.position.y,
+            self.world.player_camera.position.z,
+            self.world.player_camera.yaw,
+            self.world.player_camera.pitch,
+            self.camera_controller.mode(),
+            terrain_stats.visible,
+            terrain_stats.streamed,
+            terrain_stats.streamed - terrain_stats.visible,
+            self.sculpt_status(),
+            self.hover_status(),
+            self.noise_param.name(),
+            self.noise_param.value(&self.world.terrain.noise),
+            hours as u32,
+            (hours.fract() * 60.0) as u32,
         );
+        for error in &self.load_errors {
+            debug_text += "\n";
+            debug_text += error;
+        }
+        self.renderer.update_text(self.debug_text, &debug_text);
 
         self.renderer.render(
             app,
             &self.world.ui_camera,
             &self.world.player_camera,
+            &self.settings.lighting,
             self.settings.debug_mode_active,
         );
         self.render_time = render_timer.elapsed();
     }
 
+    /// Re-centres the visible tiles on the camera once it has moved far
+    /// enough from the tile they were last centred on.
+    fn update_streaming(&mut self) {
+        let position = self.world.player_camera.position;
+        let tile = glam::vec2(position.x, position.z) / self.world.terrain.tile_span();
+
+        if let Some(center) = self.streaming_center {
+            let offset = tile - (center.as_vec2() + 0.5);
+            if offset.abs().max_element() < 0.5 + STREAMING_HYSTERESIS {
+                return;
+            }
+        }
+
+        let center = tile.floor().as_ivec2();
+        self.streaming_center = Some(center);
+        self.renderer.update_terrain(
+            self.terrain_id,
+            &self.world.terrain,
+            center,
+            self.settings.chunk_radius,
+        );
+    }
+
     pub(crate) fn handle_close_requested(&mut self, app: &AppController) {
         self.exit(app);
     }
 
     pub(crate) fn handle_mouse_motion(&mut self, dx: f32, dy: f32) {
-        if self.lmb_pressed {
+        // Sculpting paints with the left button, so looks with the right
+        let looking = if self.sculpt_mode {
+            self.rmb_pressed
+        } else {
+            self.lmb_pressed
+        };
+        if looking {
             self.camera_controller.process_mouse(dx, dy);
             let size = self.window.inner_size();
             self.window
@@ -225,13 +444,77 @@ impl Game {
         match (key, is_pressed) {
             (KeyCode::Escape, _) => self.exit(app),
             (KeyCode::KeyF, true) => self.toggle_fullscreen(),
+            (KeyCode::F2, true) => {
+                self.export_heightmaps(app, "exports".into(), ExportLayout::Single)
+            }
             (KeyCode::Digit0, true) => {
                 self.settings.debug_mode_active = !self.settings.debug_mode_active
             }
+            (KeyCode::KeyB, true) => self.toggle_sculpt_mode(),
+            (KeyCode::KeyZ, true) if self.modifiers.control_key() => {
+                if self.modifiers.shift_key() {
+                    self.redo();
+                } else {
+                    self.undo();
+                }
+            }
+            (KeyCode::KeyY, true) if self.modifiers.control_key() => self.redo(),
+            (KeyCode::KeyN, true) => {
+                let offset = if self.modifiers.shift_key() { -1 } else { 1 };
+                self.noise_param = self.noise_param.cycle(offset);
+            }
+            (KeyCode::Comma, true) => self.adjust_noise(-1),
+            (KeyCode::Period, true) => self.adjust_noise(1),
+            (KeyCode::KeyE, true) if self.sculpt_mode => {
+                let options = self.settings.erosion;
+                self.simulate(app, "Eroded the terrain", move |terrain, seed| {
+                    erosion::erode(terrain, &options, seed)
+                });
+            }
+            (KeyCode::KeyT, true) if self.sculpt_mode => {
+                let options = self.settings.thermal;
+                self.simulate(app, "Weathered the terrain", move |terrain, seed| {
+                    thermal::weather(terrain, &options, seed)
+                });
+            }
+            (KeyCode::KeyV, true) if self.sculpt_mode => {
+                let options = self.settings.sand;
+                self.simulate(app, "Blew sand over the dunes", move |terrain, seed| {
+                    sand::blow_sand(terrain, &options, seed)
+                });
+            }
+            (_, true) if self.sculpt_mode => self.handle_brush_key(key),
+            _ => {}
+        }
+    }
+
+    fn handle_brush_key(&mut self, key: KeyCode) {
+        let brush = &mut self.sculptor.brush;
+        match key {
+            KeyCode::Digit1 => brush.kind = BrushKind::Raise,
+            KeyCode::Digit2 => brush.kind = BrushKind::Lower,
+            KeyCode::Digit3 => brush.kind = BrushKind::Smooth,
+            KeyCode::Digit4 => brush.kind = BrushKind::Flatten,
+            KeyCode::Digit5 => brush.kind = BrushKind::Noise,
+            KeyCode::Digit6 => brush.kind = BrushKind::Paint(Biome::Mountains),
+            KeyCode::Digit7 => brush.kind = BrushKind::Paint(Biome::Dunes),
+            KeyCode::Digit8 => brush.kind = BrushKind::Paint(Biome::Spires),
+            KeyCode::BracketLeft => brush.scale_radius(0.8),
+            KeyCode::BracketRight => brush.scale_radius(1.25),
+            KeyCode::Minus => brush.scale_strength(0.8),
+            KeyCode::Equal => brush.scale_strength(1.25),
             _ => {}
         }
     }
 
+    pub(crate) fn handle_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    pub(crate) fn handle_cursor_moved(&mut self, x: f32, y: f32) {
+        self.cursor = Some(glam::vec2(x, y));
+    }
+
     pub(crate) fn handle_axis(&self, axis: gilrs::Axis, _amount: f32) {
         match axis {
             _ => {}
@@ -242,7 +525,17 @@ impl Game {
         match button {
             MouseButton::Left => {
                 self.lmb_pressed = is_pressed;
-                self.window.set_cursor_visible(!is_pressed);
+                if !self.sculpt_mode {
+                    self.window.set_cursor_visible(!is_pressed);
+                } else if !is_pressed {
+                    self.sculptor.end_stroke(&self.world.terrain);
+                }
+            }
+            MouseButton::Right => {
+                self.rmb_pressed = is_pressed;
+                if self.sculpt_mode {
+                    self.window.set_cursor_visible(!is_pressed);
+                }
             }
             _ => {}
         }
@@ -252,6 +545,199 @@ impl Game {
         self.camera_controller.process_mouse_scroll(&delta);
     }
 
+    fn toggle_sculpt_mode(&mut self) {
+        self.sculpt_mode = !self.sculpt_mode;
+        self.sculptor.end_stroke(&self.world.terrain);
+        self.lmb_pressed = false;
+        self.rmb_pressed = false;
+        self.window.set_cursor_visible(true);
+    }
+
+    fn sculpt_status(&self) -> String {
+        if !self.sculpt_mode {
+            return "off".to_string();
+        }
+        let brush = &self.sculptor.brush;
+        format!(
+            "{:?} (radius {:.1}, strength {:.1}){}",
+            brush.kind,
+            brush.radius,
+            brush.strength,
+            if self.simulation.is_some() {
+                ", simulating"
+            } else {
+                ""
+            }
+        )
+    }
+
+    fn hover_status(&self) -> String {
+        let Some(hit) = &self.hovered else {
+            return "--".to_string();
+        };
+        let biome = self
+            .world
+            .terrain
+            .tile(hit.tile)
+            .map(|tile| tile.biome)
+            .unwrap_or_default();
+        format!(
+            "tile ({}, {}) at ({:.2}, {:.2}, {:.2}), biome ({:.2}, {:.2}, {:.2})",
+            hit.tile.0,
+            hit.tile.1,
+            hit.position.x,
+            hit.position.y,
+            hit.position.z,
+            biome.mountains,
+            biome.dunes,
+            biome.spires
+        )
+    }
+
+    /// Applies the brush where the cursor touches the terrain.
+    fn sculpt(&mut self, dt: f32) {
+        let Some(hit) = self.hovered else {
+            return;
+        };
+
+        let center = glam::vec2(hit.position.x, hit.position.z);
+        let tiles = self.sculptor.dab(&mut self.world.terrain, center, dt);
+        self.renderer
+            .invalidate_terrain_tiles(self.terrain_id, &self.world.terrain, &tiles);
+    }
+
+    /// Changes the selected noise parameter and re-bakes the terrain with
+    /// it.
+    fn adjust_noise(&mut self, steps: i32) {
+        let terrain = &mut self.world.terrain;
+        self.noise_param.adjust(&mut terrain.noise, steps);
+        self.renderer.update_terrain_data(self.terrain_id, terrain);
+    }
+
+    fn undo(&mut self) {
+        let tiles = self.sculptor.undo(&mut self.world.terrain);
+        self.renderer
+            .invalidate_terrain_tiles(self.terrain_id, &self.world.terrain, &tiles);
+    }
+
+    fn redo(&mut self) {
+        let tiles = self.sculptor.redo(&mut self.world.terrain);
+        self.renderer
+            .invalidate_terrain_tiles(self.terrain_id, &self.world.terrain, &tiles);
+    }
+
+    /// Runs a simulation over a copy of the terrain in the background.
+    /// Its result is applied by [`Self::finish_simulation`].
+    ///
+    /// The seed comes from the terrain's seed, the simulation's name and
+    /// how many simulations ran before it, so running the same ones in
+    /// the same order on the same terrain gives the same result, while
+    /// running one again doesn't just repeat it.
+    fn simulate(
+        &mut self,
+        app: &AppController,
+        name: &'static str,
+        run: impl FnOnce(&Terrain, u32) -> HeightChanges + Send + Sync + 'static,
+    ) {
+        if self.simulation.is_some() {
+            log::warn!("A simulation is already running");
+            return;
+        }
+
+        let seed = simulation_seed(self.world.terrain.seed, name, self.simulation_runs);
+        self.simulation_runs += 1;
+
+        let (sender, receiver) = async_channel::bounded(1);
+        self.simulation = Some(receiver);
+        app.spawn_task({
+            let terrain = self.world.terrain.clone();
+            async move {
+                let timer = Instant::now();
+                let changes = run(&terrain, seed);
+                log::info!("{name} with seed {seed} in {:?}", timer.elapsed());
+                sender.send(changes).await?;
+                Ok(())
+            }
+        });
+    }
+
+    /// Applies the background simulation once it's done, as one undo
+    /// step. Anything sculpted while it ran is kept, with the simulation's
+    /// changes added on top.
+    fn finish_simulation(&mut self) {
+        let Some(receiver) = &self.simulation else {
+            return;
+        };
+        let changes = match receiver.try_recv() {
+            Ok(changes) => changes,
+            Err(async_channel::TryRecvError::Empty) => return,
+            Err(async_channel::TryRecvError::Closed) => {
+                self.simulation = None;
+                return;
+            }
+        };
+        self.simulation = None;
+
+        let tiles = self
+            .sculptor
+            .edit(&mut self.world.terrain, |terrain| changes.apply(terrain));
+        self.renderer
+            .invalidate_terrain_tiles(self.terrain_id, &self.world.terrain, &tiles);
+    }
+
+    /// Bakes the terrain to heightmap and normal map images in `dir`, in
+    /// the background.
+    fn export_heightmaps(&self, app: &AppController, dir: PathBuf, layout: ExportLayout) {
+        app.spawn_task({
+            let terrain = self.world.terrain.clone();
+            let app = app.clone();
+            async move {
+                let timer = Instant::now();
+                let result = async {
+                    for (name, data) in export::export_heightmaps(&terrain, layout)? {
+                        app.save_binary(dir.join(name), data).await?;
+                    }
+                    anyhow::Ok(())
+                };
+                match result.await {
+                    Ok(()) => log::info!(
+                        "Exported heightmaps to {} in {:?}",
+                        dir.display(),
+                        timer.elapsed()
+                    ),
+                    Err(e) => log::error!("Could not export heightmaps: {e:?}"),
+                }
+                Ok(())
+            }
+        });
+    }
+
+    /// Exports the terrain as a mesh to `path`, in the background. The
+    /// format is picked from the file extension.
+    fn export_mesh(&self, app: &AppController, path: PathBuf, options: MeshOptions) {
+        app.spawn_task({
+            let terrain = self.world.terrain.clone();
+            let app = app.clone();
+            async move {
+                let timer = Instant::now();
+                let result = async {
+                    let format = MeshFormat::from_path(&path)?;
+                    let mesh = TerrainMesh::build(&terrain, &options)?;
+                    app.save_binary(&path, mesh.encode(format, &options)).await
+                };
+                match result.await {
+                    Ok(()) => log::info!(
+                        "Exported terrain mesh to {} in {:?}",
+                        path.display(),
+                        timer.elapsed()
+                    ),
+                    Err(e) => log::error!("Could not export terrain mesh: {e:?}"),
+                }
+                Ok(())
+            }
+        });
+    }
+
     fn toggle_fullscreen(&mut self) {
         match self.window.fullscreen() {
             Some(_) => self.window.set_fullscreen(None),
@@ -268,13 +754,17 @@ impl Game {
             let terrain = self.world.terrain.clone();
             let app = app.clone();
             async move {
-                app.save_string("settings.json", serde_json::to_string_pretty(&settings)?)
+                app.save_string(SETTINGS_PATH, serde_json::to_string_pretty(&settings)?)
                     .await?;
-                app.save_string(
-                    "terrains/default.json",
-                    serde_json::to_string_pretty(&terrain)?,
-                )
-                .await?;
+
+                let timer = Instant::now();
+                let data = file::encode(&terrain)?;
+                let len = data.len();
+                app.save_binary(TERRAIN_PATH, data).await?;
+                log::info!(
+                    "Saved terrain to {TERRAIN_PATH} ({len} bytes) in {:?}",
+                    timer.elapsed()
+                );
 
                 app.exit();
                 Ok(())
@@ -282,3 +772,13 @@ impl Game {
         });
     }
 }
+
+/// Mixes `name` and `run` into `terrain_seed` with FNV-1a, which unlike
+/// [`std::hash::Hash`] is the same on every platform and build.
+fn simulation_seed(terrain_seed: u32, name: &str, run: u32) -> u32 {
+    name.bytes()
+        .chain(run.to_le_bytes())
+        .fold(terrain_seed ^ 0x811c_9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
@@ -1,6 +1,6 @@
 use bytemuck::{Pod, Zeroable};
 
-use crate::game::render::{buffer::BackedBuffer, data::CameraData};
+use crate::game::render::{buffer::BackedBuffer, data::CameraData, materials::MaterialData};
 
 pub struct CameraBinder {
     layout: wgpu::BindGroupLayout,
@@ -176,14 +176,16 @@ impl<T> UniformBinding<T> {
     }
 }
 
-pub struct SampledTextureArrayBinder {
+/// Group 2 of the terrain render pipelines: the materials' texture array,
+/// its sampler and which layers each material is in.
+pub struct MaterialBinder {
     layout: wgpu::BindGroupLayout,
 }
 
-impl SampledTextureArrayBinder {
+impl MaterialBinder {
     pub fn new(device: &wgpu::Device) -> Self {
         let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("SampledTextureArrayBinder"),
+            label: Some("MaterialBinder"),
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -201,6 +203,26 @@ impl SampledTextureArrayBinder {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
             ],
         });
         Self { layout }
@@ -211,9 +233,11 @@ impl SampledTextureArrayBinder {
         device: &wgpu::Device,
         texture: &wgpu::TextureView,
         sampler: &wgpu::Sampler,
-    ) -> SampledTextureArrayBinding {
+        materials: &BackedBuffer<MaterialData>,
+        rule_noise: &wgpu::TextureView,
+    ) -> MaterialBinding {
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("SampledTextureArrayBinding"),
+            label: Some("MaterialBinding"),
             layout: &self.layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -224,21 +248,300 @@ impl SampledTextureArrayBinder {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: materials.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(rule_noise),
+                },
             ],
         });
-        SampledTextureArrayBinding { bind_group }
+        MaterialBinding { bind_group }
     }
-    
+
     pub(crate) fn layout(&self) -> &wgpu::BindGroupLayout {
         &self.layout
     }
 }
 
-pub struct SampledTextureArrayBinding {
+pub struct MaterialBinding {
+    bind_group: wgpu::BindGroup,
+}
+
+impl MaterialBinding {
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+}
+
+/// Group 0 of the terrain render pipelines: the terrain uniform, the
+/// baked height, normal and blend maps and the biome map.
+pub struct TerrainBinder {
+    layout: wgpu::BindGroupLayout,
+}
+
+impl TerrainBinder {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TerrainBinder"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        Self { layout }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn bind<T: Pod + Zeroable>(
+        &self,
+        device: &wgpu::Device,
+        data: &BackedBuffer<T>,
+        height_maps: &wgpu::TextureView,
+        normal_maps: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        biome_map: &wgpu::TextureView,
+        blend_maps: &wgpu::TextureView,
+    ) -> TerrainBinding {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TerrainBinding"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(height_maps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(normal_maps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(biome_map),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(blend_maps),
+                },
+            ],
+        });
+        TerrainBinding { bind_group }
+    }
+}
+
+/// Group 0 of the terrain bake pipeline: the terrain uniform, the height,
+/// normal and blend maps to write to, the tiles to bake and the biome map.
+pub struct TerrainBakeBinder {
+    layout: wgpu::BindGroupLayout,
+}
+
+impl TerrainBakeBinder {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TerrainBakeBinder"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::R32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba16Float,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba8Unorm,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        Self { layout }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn bind<T: Pod + Zeroable, J: Pod + Zeroable>(
+        &self,
+        device: &wgpu::Device,
+        data: &BackedBuffer<T>,
+        height_maps: &wgpu::TextureView,
+        normal_maps: &wgpu::TextureView,
+        jobs: &BackedBuffer<J>,
+        tile_heights: &BackedBuffer<f32>,
+        biome_map: &wgpu::TextureView,
+        blend_maps: &wgpu::TextureView,
+    ) -> TerrainBinding {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TerrainBakeBinding"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(height_maps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(normal_maps),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: jobs.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: tile_heights.buffer().as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(biome_map),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(blend_maps),
+                },
+            ],
+        });
+        TerrainBinding { bind_group }
+    }
+}
+
+pub struct TerrainBinding {
     bind_group: wgpu::BindGroup,
 }
 
-impl SampledTextureArrayBinding {
+impl TerrainBinding {
     pub fn bind_group(&self) -> &wgpu::BindGroup {
         &self.bind_group
     }
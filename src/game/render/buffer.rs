@@ -45,6 +45,10 @@ impl<T: bytemuck::Pod + bytemuck::Zeroable> BackedBuffer<T> {
         self.data.len() as _
     }
 
+    pub fn data(&self) -> &[T] {
+        &self.data
+    }
+
     pub fn batch<'a>(
         &'a mut self,
         device: &'a wgpu::Device,
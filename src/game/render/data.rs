@@ -1,6 +1,6 @@
 use bytemuck::{Pod, Zeroable};
 
-use crate::game::world::camera::Camera;
+use crate::game::world::{camera::Camera, sun::LightingOptions};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
@@ -22,6 +22,27 @@ impl CameraData {
     }
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct LightingData {
+    /// Towards the sun.
+    sun_direction: glam::Vec4,
+    /// Already scaled by the sun's intensity.
+    sun_color: glam::Vec4,
+    sky_color: glam::Vec4,
+    ground_color: glam::Vec4,
+}
+
+impl LightingData {
+    pub fn update(&mut self, lighting: &LightingOptions) {
+        let ambient = lighting.ambient_scale();
+        self.sun_direction = lighting.sun_direction().extend(0.0);
+        self.sun_color = lighting.sun_light().extend(1.0);
+        self.sky_color = (glam::Vec3::from(lighting.sky_color) * ambient).extend(1.0);
+        self.ground_color = (glam::Vec3::from(lighting.ground_color) * ambient).extend(1.0);
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct UiVertex {
@@ -60,4 +81,4 @@ impl ModelVertex {
             4 => Float32x3,
         ],
     };
-}
\ No newline at end of file
+}
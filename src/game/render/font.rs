@@ -12,6 +12,7 @@ use crate::{
     game::render::{
         bindings::{self, CameraBinder, CameraBinding},
         data::UiVertex,
+        mipmap::{self, MipGenerator},
         utils::RenderPipelineBuilder,
     },
 };
@@ -135,12 +136,10 @@ impl TextPipeline {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&device.create_sampler(
-                        &wgpu::SamplerDescriptor {
-                            min_filter: wgpu::FilterMode::Linear,
-                            mag_filter: wgpu::FilterMode::Linear,
-                            ..Default::default()
-                        },
+                    resource: wgpu::BindingResource::Sampler(&mipmap::mipmapped_sampler(
+                        device,
+                        "font_sampler",
+                        wgpu::AddressMode::ClampToEdge,
                     )),
                 },
             ],
@@ -312,6 +311,7 @@ impl Font {
         unknown_char: char,
         device: &wgpu::Device,
         queue: &wgpu::Queue,
+        mip_generator: &mut MipGenerator,
     ) -> anyhow::Result<Self> {
         let bin = app.load_binary(path).await?;
 
@@ -333,11 +333,13 @@ impl Font {
             };
             let texture = device.create_texture(&wgpu::TextureDescriptor {
                 size: texture_size,
-                mip_level_count: 1,
+                mip_level_count: mipmap::mip_level_count(dimensions.0, dimensions.1),
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
                 label: Some(&format!("{}", name.display())),
                 view_formats: &[],
             });
@@ -357,6 +359,7 @@ impl Font {
                 },
                 texture_size,
             );
+            mip_generator.generate(device, queue, &texture)?;
 
             texture
         };
@@ -0,0 +1,598 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use bytemuck::{Pod, Zeroable};
+use image::{RgbaImage, imageops::FilterType};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::AppController,
+    game::world::terrain::{
+        biome::{Biome, BiomeWeights},
+        heightfield::Rng,
+    },
+};
+
+pub const MATERIALS_PATH: &str = "materials.json";
+/// Most rules the shader has room for.
+pub const MAX_MATERIAL_RULES: usize = 16;
+/// Flat normal map texel, pointing straight out of the surface.
+const FLAT_NORMAL: [u8; 4] = [127, 127, 255, 255];
+/// Stands in for the limits of rules without them.
+const UNBOUNDED: f32 = 1e9;
+/// Width and height of [`rule_noise`], in texels.
+pub const RULE_NOISE_SIZE: u32 = 128;
+/// Units of noise [`rule_noise`] covers before it repeats. Matches
+/// `RULE_NOISE_PERIOD` in `shaders/terrain.wgsl`.
+const RULE_NOISE_PERIOD: u32 = 8;
+
+/// The materials terrain is drawn with, loaded from [`MATERIALS_PATH`].
+/// Image paths are relative to the `res` folder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaterialsFile {
+    /// Width and height every image is resized to. Defaults to the size of
+    /// the largest image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u32>,
+    pub materials: Vec<Material>,
+    /// Where each material covers the terrain, at most
+    /// [`MAX_MATERIAL_RULES`] of them. Each rule is painted over the ones
+    /// before it, and the first covers everything, whatever its limits.
+    pub rules: Vec<MaterialRule>,
+    /// Frequency, per world unit, of the noise that moves the edges of
+    /// rules.
+    #[serde(default = "default_noise_frequency")]
+    pub noise_frequency: f32,
+    /// Name of the material for spires, which covers them from their foot
+    /// up over every rule.
+    pub spire: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    pub name: String,
+    /// Colour image. Without one the material is all `color`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub albedo: Option<String>,
+    #[serde(default = "default_color")]
+    pub color: [u8; 3],
+    /// Tangent space normal map. Without one the material is flat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal: Option<String>,
+    /// Greyscale roughness image, white being fully rough. Without one the
+    /// material is fully rough.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roughness: Option<String>,
+}
+
+/// Where a material covers the terrain. Without any limits, it covers
+/// everything.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaterialRule {
+    pub material: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_altitude: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_altitude: Option<f32>,
+    /// Width, in world units, of the fade across the altitude limits.
+    #[serde(default)]
+    pub altitude_blend: f32,
+    /// Gentlest slope covered, in degrees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_slope: Option<f32>,
+    /// Steepest slope covered, in degrees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_slope: Option<f32>,
+    /// Width, in degrees, of the fade across the slope limits.
+    #[serde(default)]
+    pub slope_blend: f32,
+    /// Biome the material covers, as much as the biome's weight. Without
+    /// one, every biome.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub biome: Option<Biome>,
+    /// How far noise moves the edges, as a fraction of the blend widths,
+    /// from 0 to 1.
+    #[serde(default)]
+    pub noise: f32,
+}
+
+impl MaterialRule {
+    /// Covers everything.
+    pub fn new(material: &str) -> Self {
+        Self {
+            material: material.to_string(),
+            min_altitude: None,
+            max_altitude: None,
+            altitude_blend: 0.0,
+            min_slope: None,
+            max_slope: None,
+            slope_blend: 0.0,
+            biome: None,
+            noise: 0.0,
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("altitude_blend", self.altitude_blend),
+            ("slope_blend", self.slope_blend),
+        ] {
+            anyhow::ensure!(
+                value.is_finite() && value >= 0.0,
+                "{name} can't be negative, not {value}"
+            );
+        }
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.noise),
+            "noise must be from 0 to 1, not {}",
+            self.noise
+        );
+        for (name, min, max) in [
+            ("altitude", self.min_altitude, self.max_altitude),
+            ("slope", self.min_slope, self.max_slope),
+        ] {
+            let (min, max) = (min.unwrap_or(-UNBOUNDED), max.unwrap_or(UNBOUNDED));
+            anyhow::ensure!(
+                min.is_finite() && max.is_finite() && min <= max,
+                "min_{name} must be a number no more than max_{name}"
+            );
+        }
+        Ok(())
+    }
+}
+
+fn default_color() -> [u8; 3] {
+    [255, 255, 255]
+}
+
+fn default_noise_frequency() -> f32 {
+    0.05
+}
+
+impl Default for MaterialsFile {
+    /// Plain grass, with dirt on slopes and spire rock.
+    fn default() -> Self {
+        let material = |name: &str, color| Material {
+            name: name.to_string(),
+            albedo: None,
+            color,
+            normal: None,
+            roughness: None,
+        };
+        Self {
+            size: None,
+            materials: vec![
+                material("grass", [0x28, 0xaa, 0x00]),
+                material("dirt", [0x62, 0x3b, 0x0f]),
+                material("rock", [0xb3, 0x5a, 0x32]),
+            ],
+            rules: vec![
+                MaterialRule::new("grass"),
+                MaterialRule {
+                    min_slope: Some(0.8f32.acos().to_degrees()),
+                    ..MaterialRule::new("dirt")
+                },
+            ],
+            noise_frequency: default_noise_frequency(),
+            spire: "rock".to_string(),
+        }
+    }
+}
+
+impl MaterialsFile {
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let file = serde_json::from_str::<Self>(json)?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.materials.is_empty(), "There are no materials");
+        anyhow::ensure!(self.size != Some(0), "size can't be 0");
+
+        let mut names = HashSet::new();
+        for material in &self.materials {
+            anyhow::ensure!(
+                names.insert(material.name.as_str()),
+                "Material {:?} appears twice",
+                material.name
+            );
+        }
+        anyhow::ensure!(
+            (1..=MAX_MATERIAL_RULES).contains(&self.rules.len()),
+            "There must be from 1 to {MAX_MATERIAL_RULES} rules, not {}",
+            self.rules.len()
+        );
+        for (i, rule) in self.rules.iter().enumerate() {
+            rule.validate()
+                .with_context(|| format!("Rule {i} is invalid"))?;
+        }
+        let roles = self.rules.iter().map(|rule| ("A rule", &rule.material));
+        for (role, name) in roles.chain([("spire", &self.spire)]) {
+            anyhow::ensure!(
+                names.contains(name.as_str()),
+                "{role} uses {name:?}, which isn't one of the materials"
+            );
+        }
+        anyhow::ensure!(
+            self.noise_frequency.is_finite() && self.noise_frequency >= 0.0,
+            "noise_frequency can't be negative, not {}",
+            self.noise_frequency
+        );
+        Ok(())
+    }
+
+    /// The same materials, with their plain colours instead of images.
+    pub fn without_images(&self) -> Self {
+        let mut file = self.clone();
+        file.size = None;
+        for material in &mut file.materials {
+            material.albedo = None;
+            material.normal = None;
+            material.roughness = None;
+        }
+        file
+    }
+
+    /// First layer of the named material in the texture array.
+    fn layer(&self, name: &str) -> u32 {
+        let index = self.materials.iter().position(|m| m.name == name);
+        index.unwrap_or(0) as u32 * 2
+    }
+
+    /// Loads and resizes the images into the layers of a texture array no
+    /// wider than `max_size`.
+    pub async fn load_layers(
+        &self,
+        app: &AppController,
+        max_size: u32,
+    ) -> anyhow::Result<MaterialLayers> {
+        let mut images = Vec::with_capacity(self.materials.len());
+        for material in &self.materials {
+            let load = async |path: &Option<String>| {
+                let Some(path) = path else {
+                    return anyhow::Ok(None);
+                };
+                let data = app.load_binary(path).await?;
+                let image = image::load_from_memory(&data)
+                    .with_context(|| format!("Could not decode {path}"))?;
+                Ok(Some(image))
+            };
+            let albedo = load(&material.albedo).await?.map(|i| i.into_rgba8());
+            let normal = load(&material.normal).await?.map(|i| i.into_rgba8());
+            let roughness = load(&material.roughness).await?.map(|i| i.into_luma8());
+            images.push((albedo, normal, roughness));
+        }
+
+        let size = self.size.unwrap_or_else(|| {
+            let sizes = images.iter().flat_map(|(albedo, normal, roughness)| {
+                [
+                    albedo.as_ref().map(|i| i.dimensions()),
+                    normal.as_ref().map(|i| i.dimensions()),
+                    roughness.as_ref().map(|i| i.dimensions()),
+                ]
+            });
+            sizes.flatten().map(|(w, h)| w.max(h)).max().unwrap_or(1)
+        });
+        anyhow::ensure!(
+            size <= max_size,
+            "Materials can be at most {max_size} wide, not {size}"
+        );
+
+        let mut data = Vec::new();
+        for (material, (albedo, normal, roughness)) in self.materials.iter().zip(images) {
+            let [r, g, b] = material.color;
+            let mut albedo = fit(albedo, size, [r, g, b, 255]);
+            if let Some(roughness) = roughness {
+                let roughness =
+                    image::imageops::resize(&roughness, size, size, FilterType::Triangle);
+                for (texel, rough) in albedo.pixels_mut().zip(roughness.pixels()) {
+                    texel[3] = rough[0];
+                }
+            } else {
+                albedo.pixels_mut().for_each(|texel| texel[3] = 255);
+            }
+            data.extend_from_slice(&albedo);
+            data.extend_from_slice(&fit(normal, size, FLAT_NORMAL));
+        }
+
+        let layers = layer_count(self.materials.len());
+        data.resize((size * size * 4 * layers) as usize, 0);
+
+        Ok(MaterialLayers {
+            size,
+            layers,
+            data,
+            uniform: self.uniform(),
+        })
+    }
+
+    fn uniform(&self) -> MaterialData {
+        let mut rules = [MaterialRuleData::zeroed(); MAX_MATERIAL_RULES];
+        for (data, rule) in rules.iter_mut().zip(&self.rules) {
+            let biome = match rule.biome {
+                Some(biome) => BiomeWeights::only(biome).to_vec3().extend(0.0),
+                None => glam::Vec4::ONE,
+            };
+            *data = MaterialRuleData {
+                min_altitude: rule.min_altitude.unwrap_or(-UNBOUNDED),
+                max_altitude: rule.max_altitude.unwrap_or(UNBOUNDED),
+                altitude_blend: rule.altitude_blend,
+                noise: rule.noise,
+                min_slope: rule.min_slope.unwrap_or(-UNBOUNDED),
+                max_slope: rule.max_slope.unwrap_or(UNBOUNDED),
+                slope_blend: rule.slope_blend,
+                layer: self.layer(&rule.material),
+                biome: biome.to_array(),
+            };
+        }
+        MaterialData {
+            spire_layer: self.layer(&self.spire),
+            rule_count: self.rules.len().min(MAX_MATERIAL_RULES) as u32,
+            noise_frequency: self.noise_frequency,
+            _padding: 0,
+            rules,
+        }
+    }
+}
+
+/// Loads the materials in [`MATERIALS_PATH`], or the default ones if there
+/// isn't a file. A file that can't be loaded is reported in `load_errors`
+/// and kept. So are images that can't be loaded, which the materials'
+/// colours stand in for.
+pub async fn load(
+    app: &AppController,
+    max_size: u32,
+    load_errors: &mut Vec<String>,
+) -> anyhow::Result<MaterialLayers> {
+    let file = match app.load_string(MATERIALS_PATH).await {
+        Ok(json) => match MaterialsFile::parse(&json) {
+            Ok(file) => file,
+            Err(e) => {
+                load_errors.push(
+                    app.reject_file(
+                        MATERIALS_PATH,
+                        json.into_bytes(),
+                        &e,
+                        "Using the default materials",
+                    )
+                    .await,
+                );
+                MaterialsFile::default()
+            }
+        },
+        Err(_) => MaterialsFile::default(),
+    };
+
+    match file.load_layers(app, max_size).await {
+        Ok(layers) => Ok(layers),
+        Err(e) => {
+            log::error!("Could not load the material images: {e:?}");
+            load_errors.push(format!(
+                "Could not load the material images: {e:#}\n  Using the materials' colours"
+            ));
+            file.without_images().load_layers(app, max_size).await
+        }
+    }
+}
+
+/// Gradient noise that tiles, which moves the edges of the material
+/// rules. Texels are from 0 to 255, with no noise at 128. Each rule reads
+/// it at a different offset, so their edges don't move together.
+pub fn rule_noise() -> Vec<u8> {
+    let period = RULE_NOISE_PERIOD as usize;
+    let mut rng = Rng::new(0);
+    let gradients = (0..period * period)
+        .map(|_| glam::Vec2::from_angle(rng.next_f32() * std::f32::consts::TAU))
+        .collect::<Vec<_>>();
+
+    let scale = RULE_NOISE_PERIOD as f32 / RULE_NOISE_SIZE as f32;
+    (0..RULE_NOISE_SIZE * RULE_NOISE_SIZE)
+        .map(|i| {
+            let texel = glam::uvec2(i % RULE_NOISE_SIZE, i / RULE_NOISE_SIZE);
+            let p = (texel.as_vec2() + 0.5) * scale;
+            let cell = p.floor();
+            let f = p - cell;
+            // Wrapping the lattice around makes the noise tile
+            let corner = |dx: usize, dz: usize| {
+                let x = (cell.x as usize + dx) % period;
+                let z = (cell.y as usize + dz) % period;
+                gradients[x + z * period].dot(f - glam::vec2(dx as f32, dz as f32))
+            };
+            let t = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+            let n0 = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * t.x;
+            let n1 = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * t.x;
+            let n = n0 + (n1 - n0) * t.y;
+            // Gradient noise stays within about 0.7 of zero
+            ((n / 0.7).clamp(-1.0, 1.0) * 127.5 + 127.5).round() as u8
+        })
+        .collect()
+}
+
+/// Layers of the texture array for `materials` materials, two each plus
+/// any padding.
+fn layer_count(materials: usize) -> u32 {
+    // GL makes square textures with a multiple of six layers into cube
+    // maps, which can't be sampled as arrays
+    let layers = materials as u32 * 2;
+    if layers.is_multiple_of(6) {
+        layers + 2
+    } else {
+        layers
+    }
+}
+
+/// `image` resized to `size` by `size`, or all `fill` without one.
+fn fit(image: Option<RgbaImage>, size: u32, fill: [u8; 4]) -> RgbaImage {
+    match image {
+        Some(image) if image.dimensions() == (size, size) => image,
+        Some(image) => image::imageops::resize(&image, size, size, FilterType::Triangle),
+        None => RgbaImage::from_pixel(size, size, image::Rgba(fill)),
+    }
+}
+
+/// Texels of the terrain texture array, layer by layer. Each material has
+/// two layers: its albedo with its roughness in alpha, then its normal
+/// map.
+pub struct MaterialLayers {
+    pub size: u32,
+    pub layers: u32,
+    pub data: Vec<u8>,
+    pub uniform: MaterialData,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct MaterialData {
+    /// First layer of the material for spires.
+    spire_layer: u32,
+    rule_count: u32,
+    noise_frequency: f32,
+    _padding: u32,
+    rules: [MaterialRuleData; MAX_MATERIAL_RULES],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct MaterialRuleData {
+    min_altitude: f32,
+    max_altitude: f32,
+    altitude_blend: f32,
+    noise: f32,
+    /// Slopes are in degrees.
+    min_slope: f32,
+    max_slope: f32,
+    slope_blend: f32,
+    /// First layer of the rule's material.
+    layer: u32,
+    /// Multiplied by the biome blend, so that's how much the rule covers.
+    biome: [f32; 4],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_error(rule: MaterialRule) -> String {
+        let file = MaterialsFile {
+            rules: vec![MaterialRule::new("grass"), rule],
+            ..Default::default()
+        };
+        format!("{:#}", file.validate().unwrap_err())
+    }
+
+    #[test]
+    fn default_and_shipped_materials_are_valid() {
+        MaterialsFile::default().validate().unwrap();
+
+        let path = format!("{}/res/{MATERIALS_PATH}", env!("CARGO_MANIFEST_DIR"));
+        let json = std::fs::read_to_string(path).unwrap();
+        MaterialsFile::parse(&json).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let error = |edit: fn(&mut MaterialsFile)| {
+            let mut file = MaterialsFile::default();
+            edit(&mut file);
+            file.validate().unwrap_err().to_string()
+        };
+        assert!(error(|f| f.materials.clear()).contains("no materials"));
+        assert!(error(|f| f.size = Some(0)).contains("size"));
+        assert!(error(|f| f.materials[1].name = "grass".into()).contains("twice"));
+        assert!(error(|f| f.rules.clear()).contains("from 1 to 16"));
+        assert!(error(|f| f.rules.resize(17, MaterialRule::new("grass"))).contains("not 17"));
+        assert!(error(|f| f.rules[1].material = "snow".into()).contains("\"snow\""));
+        assert!(error(|f| f.spire = "snow".into()).contains("spire"));
+        assert!(error(|f| f.noise_frequency = -1.0).contains("noise_frequency"));
+
+        let mut file = MaterialsFile::default();
+        file.rules
+            .resize(MAX_MATERIAL_RULES, MaterialRule::new("dirt"));
+        file.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let error = rule_error(MaterialRule {
+            altitude_blend: -1.0,
+            ..MaterialRule::new("dirt")
+        });
+        assert!(
+            error.contains("Rule 1") && error.contains("altitude_blend"),
+            "{error}"
+        );
+        let error = rule_error(MaterialRule {
+            noise: 1.5,
+            ..MaterialRule::new("dirt")
+        });
+        assert!(error.contains("noise"), "{error}");
+        let error = rule_error(MaterialRule {
+            min_slope: Some(40.0),
+            max_slope: Some(30.0),
+            ..MaterialRule::new("dirt")
+        });
+        assert!(error.contains("min_slope"), "{error}");
+        let error = rule_error(MaterialRule {
+            max_altitude: Some(f32::NAN),
+            ..MaterialRule::new("dirt")
+        });
+        assert!(error.contains("min_altitude"), "{error}");
+
+        // One sided limits are fine
+        MaterialRule {
+            min_altitude: Some(10.0),
+            max_slope: Some(30.0),
+            ..MaterialRule::new("dirt")
+        }
+        .validate()
+        .unwrap();
+    }
+
+    #[test]
+    fn rules_point_at_material_layers() {
+        let file = MaterialsFile {
+            rules: vec![
+                MaterialRule::new("rock"),
+                MaterialRule {
+                    max_altitude: Some(5.0),
+                    biome: Some(Biome::Dunes),
+                    ..MaterialRule::new("dirt")
+                },
+            ],
+            ..Default::default()
+        };
+        let data = file.uniform();
+        assert_eq!(data.spire_layer, 4);
+        assert_eq!(data.rule_count, 2);
+        assert_eq!([data.rules[0].layer, data.rules[1].layer], [4, 2]);
+
+        let rule = data.rules[1];
+        assert_eq!(rule.biome, [0.0, 1.0, 0.0, 0.0]);
+        assert_eq!((rule.min_altitude, rule.max_altitude), (-UNBOUNDED, 5.0));
+        assert_eq!(data.rules[0].biome, [1.0; 4]);
+    }
+
+    #[test]
+    fn rule_noise_tiles() {
+        let noise = rule_noise();
+        let size = RULE_NOISE_SIZE as usize;
+        let at = |x: usize, z: usize| noise[x % size + z % size * size] as i32;
+        // Neighbours across the edges are as close as neighbours inside
+        for i in 0..size {
+            assert!((at(size - 1, i) - at(0, i)).abs() < 16);
+            assert!((at(i, size - 1) - at(i, 0)).abs() < 16);
+        }
+        let (min, max) = (noise.iter().min().unwrap(), noise.iter().max().unwrap());
+        assert!(*min < 64 && *max > 192, "{min}..{max}");
+    }
+
+    #[test]
+    fn layers_skip_multiples_of_six() {
+        assert_eq!(layer_count(1), 2);
+        assert_eq!(layer_count(2), 4);
+        assert_eq!(layer_count(3), 8);
+        assert_eq!(layer_count(5), 10);
+        assert_eq!(layer_count(6), 14);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use crate::{app::AppController, game::render::utils::RenderPipelineBuilder};
+
+/// Fills in the mip levels of textures from their first level, by drawing
+/// each level from the one above it with a linear filter. Textures need
+/// [`wgpu::TextureUsages::RENDER_ATTACHMENT`] for this.
+pub struct MipGenerator {
+    shader: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    /// Pipelines made so far, by the format they draw to and whether they
+    /// read texture arrays.
+    pipelines: HashMap<(wgpu::TextureFormat, bool), wgpu::RenderPipeline>,
+}
+
+impl MipGenerator {
+    pub async fn new(app: &AppController, device: &wgpu::Device) -> anyhow::Result<Self> {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/mipmap.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(app.load_string("shaders/mipmap.wgsl").await?.into()),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("MipGenerator"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Ok(Self {
+            shader,
+            sampler,
+            pipelines: HashMap::new(),
+        })
+    }
+
+    /// Generates every mip level of `texture` after the first, for every
+    /// layer.
+    pub fn generate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) -> anyhow::Result<()> {
+        if texture.mip_level_count() <= 1 {
+            return Ok(());
+        }
+
+        // Arrays have to be read as arrays, as GL can't view one layer of
+        // them as a plain texture
+        let layers = texture.depth_or_array_layers();
+        let array = layers > 1;
+        let key = (texture.format(), array);
+        if !self.pipelines.contains_key(&key) {
+            let pipeline = self.create_pipeline(device, texture.format(), array)?;
+            self.pipelines.insert(key, pipeline);
+        }
+        let pipeline = &self.pipelines[&key];
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("MipGenerator"),
+        });
+        for level in 1..texture.mip_level_count() {
+            let source = texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(if array {
+                    wgpu::TextureViewDimension::D2Array
+                } else {
+                    wgpu::TextureViewDimension::D2
+                }),
+                base_mip_level: level - 1,
+                mip_level_count: Some(1),
+                ..Default::default()
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("MipGenerator"),
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: if array { 2 } else { 0 },
+                        resource: wgpu::BindingResource::TextureView(&source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+
+            for layer in 0..layers {
+                let target = texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: level,
+                    mip_level_count: Some(1),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("MipGenerator"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    ..Default::default()
+                });
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                // The instance picks the layer to read
+                pass.draw(0..3, layer..layer + 1);
+            }
+        }
+        queue.submit([encoder.finish()]);
+
+        Ok(())
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        array: bool,
+    ) -> anyhow::Result<wgpu::RenderPipeline> {
+        RenderPipelineBuilder::new()
+            .label("MipGenerator")
+            .vertex(wgpu::VertexState {
+                module: &self.shader,
+                entry_point: Some("fullscreen"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            })
+            .fragment(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: Some(if array {
+                    "downsample_array"
+                } else {
+                    "downsample"
+                }),
+                compilation_options: Default::default(),
+                targets: &[Some(format.into())],
+            })
+            .build(device)
+    }
+}
+
+/// Sampler that blends between mip levels and filters surfaces seen at a
+/// glancing angle, for textures with mipmaps.
+pub fn mipmapped_sampler(
+    device: &wgpu::Device,
+    label: &str,
+    address_mode: wgpu::AddressMode,
+) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some(label),
+        address_mode_u: address_mode,
+        address_mode_v: address_mode,
+        address_mode_w: address_mode,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        anisotropy_clamp: 16,
+        ..Default::default()
+    })
+}
+
+/// Mip levels a `width` by `height` texture has, down to one texel.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    }
+    .max_mips(wgpu::TextureDimension::D2)
+}
@@ -2,6 +2,8 @@ pub mod bindings;
 pub mod buffer;
 pub mod data;
 pub mod font;
+pub mod materials;
+pub mod mipmap;
 pub mod pipeline;
 pub mod terrain;
 pub mod utils;
@@ -9,7 +11,7 @@ pub mod utils;
 use std::sync::Arc;
 
 use anyhow::Context;
-use wgpu::util::DeviceExt;
+use bytemuck::Zeroable;
 use winit::window::Window;
 
 use crate::{
@@ -17,14 +19,16 @@ use crate::{
     game::{
         render::{
             bindings::{
-                CameraBinder, SampledTextureArrayBinder, SampledTextureBinder, UniformBinder,
+                CameraBinder, MaterialBinder, SampledTextureBinder, TerrainBakeBinder,
+                TerrainBinder, UniformBinder, UniformBinding,
             },
             buffer::BackedBuffer,
-            data::CameraData,
+            data::{CameraData, LightingData},
             font::{Font, TextPipeline},
-            terrain::{TerrainBuffer, TerrainPipeline, TileInstance},
+            mipmap::{MipGenerator, mipmapped_sampler},
+            terrain::{TerrainBuffer, TerrainPipeline, TerrainStats},
         },
-        world::{camera::Camera, terrain::Terrain},
+        world::{camera::Camera, sun::LightingOptions, terrain::Terrain},
     },
 };
 
@@ -41,14 +45,19 @@ pub struct Renderer {
     text_buffers: Vec<font::TextBuffer>,
     ui_camera_buffer: BackedBuffer<CameraData>,
     ui_camera_binding: bindings::CameraBinding,
-    terrain_binder: UniformBinder<terrain::TerrainData>,
+    terrain_binder: TerrainBinder,
+    terrain_bake_binder: TerrainBakeBinder,
     terrain_pipeline: TerrainPipeline,
     terrain_buffers: Vec<TerrainBuffer>,
     depth_buffer: wgpu::Texture,
     depth_buffer_view: wgpu::TextureView,
     main_camera_buffer: BackedBuffer<CameraData>,
     main_camera_binding: bindings::CameraBinding,
-    terrain_texture_binding: bindings::SampledTextureArrayBinding,
+    terrain_texture_binding: bindings::MaterialBinding,
+    lighting_buffer: BackedBuffer<LightingData>,
+    lighting_binding: UniformBinding<LightingData>,
+    /// Why files couldn't be loaded, for the game to show.
+    pub(crate) load_errors: Vec<String>,
     // time_query_set: wgpu::QuerySet,
 }
 
@@ -85,6 +94,12 @@ impl Renderer {
 
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
+                // Every streamed tile needs its own layer of the baked
+                // terrain maps, so take as many as we can get.
+                required_limits: wgpu::Limits {
+                    max_texture_array_layers: adapter.limits().max_texture_array_layers,
+                    ..Default::default()
+                },
                 ..Default::default()
             })
             .await?;
@@ -100,7 +115,17 @@ impl Renderer {
         let camera_binder = CameraBinder::new(&device);
         let sampled_texture_binder = SampledTextureBinder::new(&device);
 
-        let font = Font::load(app, "fonts/OpenSans MSDF.zip", '�', &device, &queue).await?;
+        let mut mip_generator = MipGenerator::new(app, &device).await?;
+
+        let font = Font::load(
+            app,
+            "fonts/OpenSans MSDF.zip",
+            '�',
+            &device,
+            &queue,
+            &mut mip_generator,
+        )
+        .await?;
         let text_pipeline = TextPipeline::new(
             app,
             &device,
@@ -125,7 +150,14 @@ impl Renderer {
         );
         let main_camera_binding = camera_binder.bind(&device, &main_camera_buffer);
 
-        let texture_array_binder = SampledTextureArrayBinder::new(&device);
+        let material_binder = MaterialBinder::new(&device);
+        let lighting_binder = UniformBinder::new(&device, wgpu::ShaderStages::FRAGMENT);
+        let lighting_buffer = BackedBuffer::with_data(
+            &device,
+            vec![LightingData::zeroed()],
+            wgpu::BufferUsages::UNIFORM,
+        );
+        let lighting_binding = lighting_binder.bind(&device, &lighting_buffer);
 
         let depth_format = wgpu::TextureFormat::Depth32Float;
         let depth_buffer = device.create_texture(&wgpu::TextureDescriptor {
@@ -144,46 +176,95 @@ impl Renderer {
         });
         let depth_buffer_view = depth_buffer.create_view(&Default::default());
 
-        let terrain_binder = UniformBinder::new(&device, wgpu::ShaderStages::VERTEX_FRAGMENT);
+        let terrain_binder = TerrainBinder::new(&device);
+        let terrain_bake_binder = TerrainBakeBinder::new(&device);
         let terrain_pipeline = TerrainPipeline::new(
             app,
             &device,
             &terrain_binder,
+            &terrain_bake_binder,
             &camera_binder,
-            &texture_array_binder,
+            &material_binder,
+            &lighting_binder,
             config.format,
             depth_format,
         )
         .await?;
 
-        let terrain_texture_array = device.create_texture_with_data(
-            &queue,
-            &wgpu::TextureDescriptor {
-                label: Some("terrain_texture_array"),
-                size: wgpu::Extent3d {
-                    width: 1,
-                    height: 1,
-                    depth_or_array_layers: 4,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
+        let mut load_errors = Vec::new();
+        let max_size = device.limits().max_texture_dimension_2d;
+        let materials = materials::load(app, max_size, &mut load_errors).await?;
+        let terrain_texture_size = wgpu::Extent3d {
+            width: materials.size,
+            height: materials.size,
+            depth_or_array_layers: materials.layers,
+        };
+        let terrain_texture_array = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("terrain_texture_array"),
+            size: terrain_texture_size,
+            mip_level_count: mipmap::mip_level_count(materials.size, materials.size),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            terrain_texture_array.as_image_copy(),
+            &materials.data,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * materials.size),
+                rows_per_image: Some(materials.size),
             },
-            wgpu::wgt::TextureDataOrder::LayerMajor,
-            &[
-                0x28, 0xaa, 0x00, 0xff, 127, 127, 255, 255, 0x62, 0x3b, 15, 0xff, 127, 127, 255,
-                255,
-            ],
+            terrain_texture_size,
+        );
+        mip_generator.generate(&device, &queue, &terrain_texture_array)?;
+        let material_buffer = BackedBuffer::with_data(
+            &device,
+            vec![materials.uniform],
+            wgpu::BufferUsages::UNIFORM,
         );
         let terrain_texture_array_view = terrain_texture_array.create_view(&Default::default());
-        let terrain_texture_sampler = device.create_sampler(&Default::default());
-        let terrain_texture_binding = texture_array_binder.bind(
+        // Materials tile across the terrain
+        let terrain_texture_sampler = mipmapped_sampler(
+            &device,
+            "terrain_texture_sampler",
+            wgpu::AddressMode::Repeat,
+        );
+        let rule_noise_size = wgpu::Extent3d {
+            width: materials::RULE_NOISE_SIZE,
+            height: materials::RULE_NOISE_SIZE,
+            depth_or_array_layers: 1,
+        };
+        let rule_noise = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("rule_noise"),
+            size: rule_noise_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            rule_noise.as_image_copy(),
+            &materials::rule_noise(),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(materials::RULE_NOISE_SIZE),
+                rows_per_image: Some(materials::RULE_NOISE_SIZE),
+            },
+            rule_noise_size,
+        );
+        let terrain_texture_binding = material_binder.bind(
             &device,
             &terrain_texture_array_view,
             &terrain_texture_sampler,
+            &material_buffer,
+            &rule_noise.create_view(&Default::default()),
         );
 
         // let time_query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
@@ -210,9 +291,13 @@ impl Renderer {
             depth_buffer,
             depth_buffer_view,
             terrain_binder,
+            terrain_bake_binder,
             terrain_pipeline,
             terrain_buffers: Vec::new(),
             terrain_texture_binding,
+            lighting_buffer,
+            lighting_binding,
+            load_errors,
             // time_query_set,
         })
     }
@@ -249,6 +334,7 @@ impl Renderer {
         app: &AppController,
         ui_camera: &impl Camera,
         player_camera: &impl Camera,
+        lighting: &LightingOptions,
         debug_mode_active: bool,
     ) {
         if !self.is_surface_configured {
@@ -274,12 +360,19 @@ impl Renderer {
             .update(&self.queue, |data| data[0].update(ui_camera));
         self.main_camera_buffer
             .update(&self.queue, |data| data[0].update(player_camera));
+        self.lighting_buffer
+            .update(&self.queue, |data| data[0].update(lighting));
 
         let view = frame.texture.create_view(&Default::default());
 
         let mut encoder = self.device.create_command_encoder(&Default::default());
         // encoder.resolve_query_set(query_set, query_range, destination, destination_offset);
 
+        for buffer in &mut self.terrain_buffers {
+            buffer.prepare_bake(&self.device, &self.queue);
+            self.terrain_pipeline.bake(&mut encoder, buffer);
+        }
+
         {
             let mut main_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("main_pass"),
@@ -312,6 +405,7 @@ impl Renderer {
                         &mut main_pass,
                         &self.main_camera_binding,
                         &self.terrain_texture_binding,
+                        &self.lighting_binding,
                         buffer,
                     );
                 }
@@ -344,35 +438,90 @@ impl Renderer {
         frame.present();
     }
 
-    pub fn buffer_terrain(&mut self, terrain: &Terrain) -> usize {
+    /// Creates the GPU buffers for a terrain. Tiles further than
+    /// `lod_distance` world units from the camera are drawn at lower
+    /// levels of detail. Enough space is set aside to bake every tile
+    /// within `chunk_radius` tiles of the camera.
+    pub fn buffer_terrain(
+        &mut self,
+        terrain: &Terrain,
+        lod_distance: f32,
+        chunk_radius: u32,
+    ) -> usize {
+        let radius = chunk_radius as i32;
+        let tiles_in_radius = (-radius..=radius)
+            .flat_map(|z| (-radius..=radius).map(move |x| glam::ivec2(x, z)))
+            .filter(|offset| offset.length_squared() <= radius * radius)
+            .count() as u32;
+        let max_layers = self.device.limits().max_texture_array_layers;
+        let num_layers = tiles_in_radius.min(terrain.tiles.len() as u32);
+        if num_layers > max_layers {
+            log::warn!(
+                "{num_layers} tiles can be streamed in, but only {max_layers} can be baked at once"
+            );
+        }
+
         let id = self.terrain_buffers.len();
         let buffer = TerrainBuffer::new(
             &self.device,
             &self.terrain_binder,
-            terrain.tile_size,
-            terrain.mountain_height,
-            terrain.dune_height,
-            terrain.spire_height,
+            &self.terrain_bake_binder,
+            terrain,
+            lod_distance,
+            num_layers.clamp(1, max_layers),
         );
         self.terrain_buffers.push(buffer);
 
         id
     }
 
-    pub fn update_terrain(&mut self, terrain_id: usize, terrain: &Terrain, chunk_radius: u32) {
-        let buffer = &mut self.terrain_buffers[terrain_id];
-        buffer.tiles.clear();
-        let mut batch = buffer.tiles.batch(&self.device, &self.queue);
-        let range = 0..chunk_radius;
-        for tile in &terrain.tiles {
-            if range.contains(&tile.id.0) && range.contains(&tile.id.1) {
-                let position = glam::vec2(
-                    (tile.id.0 * (terrain.tile_size - 1)) as _,
-                    (tile.id.1 * (terrain.tile_size - 1)) as _,
-                );
-                batch.push(TileInstance { position });
-            }
-        }
+    /// Streams in every tile within `chunk_radius` tiles of `center`.
+    /// Which of those get drawn is decided by [`Self::cull_terrain`].
+    pub fn update_terrain(
+        &mut self,
+        terrain_id: usize,
+        terrain: &Terrain,
+        center: glam::IVec2,
+        chunk_radius: u32,
+    ) {
+        let radius = chunk_radius as i32;
+        let offset = |id: (u32, u32)| glam::ivec2(id.0 as _, id.1 as _) - center;
+        let mut tiles = terrain
+            .tiles
+            .iter()
+            .map(|tile| tile.id)
+            .filter(|&id| offset(id).length_squared() <= radius * radius)
+            .collect::<Vec<_>>();
+        // Nearest first, so they get baked first
+        tiles.sort_by_key(|&id| offset(id).length_squared());
+        log::debug!("Streamed {} tiles around {center}", tiles.len());
+        self.terrain_buffers[terrain_id].set_streamed(terrain, tiles);
+    }
+
+    /// Re-bakes tiles whose heights have been edited.
+    pub fn invalidate_terrain_tiles(
+        &mut self,
+        terrain_id: usize,
+        terrain: &Terrain,
+        ids: &[(u32, u32)],
+    ) {
+        self.terrain_buffers[terrain_id].invalidate(terrain, ids);
+    }
+
+    /// Uploads a terrain's biome heights, noise and seed after they have
+    /// changed, and re-bakes it.
+    pub fn update_terrain_data(&mut self, terrain_id: usize, terrain: &Terrain) {
+        self.terrain_buffers[terrain_id].update_terrain_data(&self.queue, terrain);
+    }
+
+    /// Updates which streamed tiles are visible from `camera`. Call this
+    /// every frame before [`Self::render`].
+    pub fn cull_terrain(&mut self, terrain_id: usize, camera: &impl Camera) {
+        self.terrain_buffers[terrain_id].cull(&self.device, &self.queue, camera);
+    }
+
+    pub fn terrain_stats(&self, terrain_id: usize) -> TerrainStats {
+        self.terrain_buffers[terrain_id].stats()
     }
 
     pub fn buffer_text(&mut self, text: &str) -> usize {
@@ -1,21 +1,33 @@
+use std::{collections::HashMap, ops::Range};
+
 use bytemuck::{Pod, Zeroable};
 
 use crate::{
     app::AppController,
-    game::render::{
-        bindings::{
-            CameraBinder, CameraBinding, SampledTextureArrayBinder, SampledTextureArrayBinding,
-            UniformBinder, UniformBinding,
+    game::{
+        render::{
+            bindings::{
+                CameraBinder, CameraBinding, MaterialBinder, MaterialBinding, TerrainBakeBinder,
+                TerrainBinder, TerrainBinding, UniformBinder, UniformBinding,
+            },
+            buffer::BackedBuffer,
+            data::LightingData,
+            utils::RenderPipelineBuilder,
+        },
+        world::{
+            camera::Camera,
+            terrain::{HeightMode, Terrain, TileHeights},
         },
-        buffer::BackedBuffer,
-        utils::RenderPipelineBuilder,
     },
 };
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
 pub struct TileInstance {
     pub position: glam::Vec2,
+    pub lod: u32,
+    /// Layer of the baked height and normal maps holding this tile.
+    pub layer: u32,
 }
 
 impl TileInstance {
@@ -24,10 +36,47 @@ impl TileInstance {
         step_mode: wgpu::VertexStepMode::Instance,
         attributes: &wgpu::vertex_attr_array![
             0 => Float32x2,
+            1 => Uint32,
+            2 => Uint32,
         ],
     };
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct BakeJob {
+    tile_offset: glam::Vec2,
+    layer: u32,
+    height_mode: u32,
+    heights_offset: u32,
+    _padding: u32,
+}
+
+impl BakeJob {
+    // Matches the constants in `shaders/terrain_bake.wgsl`
+    const HEIGHTS_NONE: u32 = 0;
+    const HEIGHTS_OFFSET: u32 = 1;
+    const HEIGHTS_REPLACE: u32 = 2;
+}
+
+/// Most levels of detail a terrain will use. Each level halves the
+/// resolution of the one before it.
+const MAX_LOD_LEVELS: u32 = 6;
+/// Fraction of a level's distance range after which its vertices start
+/// morphing into the next level.
+const MORPH_START: f32 = 0.7;
+/// Depth of the skirts hanging off each tile, relative to the tallest
+/// feature of the terrain. Skirts hide cracks between levels of detail.
+const SKIRT_DEPTH: f32 = 0.05;
+/// Most tiles baked in one frame, so streaming in a lot of tiles at once
+/// doesn't stall a frame.
+const MAX_BAKES_PER_FRAME: u32 = 64;
+/// Most tiles with their own height samples baked in one frame. This
+/// bounds the size of the buffer the samples are uploaded through.
+const MAX_HEIGHT_BAKES_PER_FRAME: u32 = 8;
+/// Matches `@workgroup_size` of `bake_tile`.
+const BAKE_WORKGROUP_SIZE: u32 = 8;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct TerrainData {
@@ -35,93 +84,669 @@ pub struct TerrainData {
     mountain_height: f32,
     dune_height: f32,
     spire_height: f32,
+    lod_distance: f32,
+    morph_start: f32,
+    skirt_depth: f32,
+    lod_count: f32,
+    mountain_octaves: f32,
+    mountain_frequency: f32,
+    mountain_lacunarity: f32,
+    mountain_gain: f32,
+    mountain_rotation: f32,
+    dune_frequency: f32,
+    dune_warp_frequency: f32,
+    dune_warp_amplitude: f32,
+    spire_frequency: f32,
+    spire_radius: f32,
+    spire_terraces: f32,
+    spire_roughness: f32,
+    spire_coverage: f32,
+    spire_mask_frequency: f32,
+    seed: u32,
+    _padding: u32,
+}
+
+impl TerrainData {
+    /// Copies everything that comes from the terrain itself, rather than
+    /// from how it is drawn.
+    fn set_terrain(&mut self, terrain: &Terrain) {
+        let noise = &terrain.noise;
+        self.tile_size = terrain.tile_size as f32;
+        self.mountain_height = terrain.mountain_height;
+        self.dune_height = terrain.dune_height;
+        self.spire_height = terrain.spire_height;
+        self.skirt_depth = skirt_depth(terrain);
+        self.mountain_octaves = noise.mountains.octaves as f32;
+        self.mountain_frequency = noise.mountains.frequency;
+        self.mountain_lacunarity = noise.mountains.lacunarity;
+        self.mountain_gain = noise.mountains.gain;
+        self.mountain_rotation = noise.mountains.rotation;
+        self.dune_frequency = noise.dunes.frequency;
+        self.dune_warp_frequency = noise.dunes.warp_frequency;
+        self.dune_warp_amplitude = noise.dunes.warp_amplitude;
+        self.spire_frequency = noise.spires.frequency;
+        self.spire_radius = noise.spires.radius;
+        self.spire_terraces = noise.spires.terraces as f32;
+        self.spire_roughness = noise.spires.roughness;
+        self.spire_coverage = noise.spires.coverage;
+        self.spire_mask_frequency = noise.spires.mask_frequency;
+        self.seed = terrain.seed;
+    }
+}
+
+/// How many of the streamed tiles survived culling last frame.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TerrainStats {
+    pub streamed: u32,
+    pub visible: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TileLayer {
+    layer: u32,
+    baked: bool,
+    /// Lowest and highest the tile's surface can be.
+    min_height: f32,
+    max_height: f32,
+}
+
+/// A tile waiting to be baked, with a copy of its height samples.
+struct UnbakedTile {
+    id: (u32, u32),
+    heights: Option<TileHeights>,
 }
 
 pub struct TerrainBuffer {
-    indices: BackedBuffer<u32>,
-    pub tiles: BackedBuffer<TileInstance>,
+    lods: Vec<BackedBuffer<u32>>,
+    tiles: BackedBuffer<TileInstance>,
+    lod_ranges: Vec<Range<u32>>,
+    lod_distance: f32,
+    tile_span: f32,
+    height_range: Range<f32>,
+    skirt_depth: f32,
+    /// Tiles in range of the camera, before culling.
+    streamed: Vec<(u32, u32)>,
+    /// Which layer of the baked maps each streamed tile has.
+    layers: HashMap<(u32, u32), TileLayer>,
+    free_layers: Vec<u32>,
+    /// Tiles with a layer that still need baking, nearest first.
+    unbaked: Vec<UnbakedTile>,
+    bake_jobs: BackedBuffer<BakeJob>,
+    tile_heights: BackedBuffer<f32>,
     terrain_data: BackedBuffer<TerrainData>,
-    binding: UniformBinding<TerrainData>,
-    // todo: textures
+    terrain_size: u32,
+    biome_map: wgpu::Texture,
+    /// What `biome_map` holds, or should once it is uploaded.
+    biomes: Vec<glam::Vec4>,
+    biomes_changed: bool,
+    binding: TerrainBinding,
+    bake_binding: TerrainBinding,
 }
 
 impl TerrainBuffer {
+    /// `num_layers` is how many tiles can be baked at once, so it should
+    /// be at least the number of tiles that get streamed in.
     pub fn new(
         device: &wgpu::Device,
-        binder: &UniformBinder<TerrainData>,
-        tile_size: u32,
-        mountain_height: f32,
-        dune_height: f32,
-        spire_height: f32,
+        binder: &TerrainBinder,
+        bake_binder: &TerrainBakeBinder,
+        terrain: &Terrain,
+        lod_distance: f32,
+        num_layers: u32,
     ) -> Self {
-        let mut index_data = Vec::new();
-        for z in 0..tile_size - 1 {
-            for x in 0..tile_size - 1 {
-                let i = x + z * tile_size;
-                index_data.push(i);
-                index_data.push(i + 1 + tile_size);
-                index_data.push(i + 1);
-                index_data.push(i);
-                index_data.push(i + tile_size);
-                index_data.push(i + 1 + tile_size);
-            }
-        }
-        let indices = BackedBuffer::with_data(&device, index_data, wgpu::BufferUsages::INDEX);
-        let tiles = BackedBuffer::with_capacity(&device, 8, wgpu::BufferUsages::VERTEX);
-        let terrain_data = BackedBuffer::with_data(
-            &device,
-            vec![TerrainData {
-                tile_size: tile_size as f32,
-                mountain_height,
-                dune_height,
-                spire_height,
-            }],
-            wgpu::BufferUsages::UNIFORM,
+        let tile_size = terrain.tile_size;
+        // Loaded and imported terrains have tiles at least 2 wide, but
+        // anything narrower still gets one level of detail
+        let lod_count = tile_size
+            .saturating_sub(1)
+            .checked_ilog2()
+            .map_or(1, |log| (log + 1).min(MAX_LOD_LEVELS));
+        let lods = (0..lod_count)
+            .map(|lod| {
+                BackedBuffer::with_data(
+                    device,
+                    lod_indices(tile_size, lod),
+                    wgpu::BufferUsages::INDEX,
+                )
+            })
+            .collect();
+        let tiles = BackedBuffer::with_capacity(device, 8, wgpu::BufferUsages::VERTEX);
+        let mut data = TerrainData {
+            lod_distance,
+            morph_start: MORPH_START,
+            lod_count: lod_count as f32,
+            ..Zeroable::zeroed()
+        };
+        data.set_terrain(terrain);
+        let terrain_data = BackedBuffer::with_data(device, vec![data], wgpu::BufferUsages::UNIFORM);
+
+        let baked_map = |label, format| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: tile_size,
+                    height: tile_size,
+                    depth_or_array_layers: num_layers,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        };
+        let array_view = wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        };
+        let height_maps =
+            baked_map("height_maps", wgpu::TextureFormat::R32Float).create_view(&array_view);
+        let normal_maps =
+            baked_map("normal_maps", wgpu::TextureFormat::Rgba16Float).create_view(&array_view);
+        let blend_maps =
+            baked_map("blend_maps", wgpu::TextureFormat::Rgba8Unorm).create_view(&array_view);
+        let normal_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let biome_map = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("biome_map"),
+            size: wgpu::Extent3d {
+                width: terrain.size,
+                height: terrain.size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let biome_map_view = biome_map.create_view(&Default::default());
+
+        let bake_jobs = BackedBuffer::with_capacity(
+            device,
+            MAX_BAKES_PER_FRAME as _,
+            wgpu::BufferUsages::STORAGE,
+        );
+        let tile_heights = BackedBuffer::with_capacity(
+            device,
+            (MAX_HEIGHT_BAKES_PER_FRAME * tile_size * tile_size) as _,
+            wgpu::BufferUsages::STORAGE,
         );
 
-        let binding = binder.bind(device, &terrain_data);
+        let binding = binder.bind(
+            device,
+            &terrain_data,
+            &height_maps,
+            &normal_maps,
+            &normal_sampler,
+            &biome_map_view,
+            &blend_maps,
+        );
+        let bake_binding = bake_binder.bind(
+            device,
+            &terrain_data,
+            &height_maps,
+            &normal_maps,
+            &bake_jobs,
+            &tile_heights,
+            &biome_map_view,
+            &blend_maps,
+        );
 
         Self {
-            indices,
+            lods,
             tiles,
+            lod_ranges: Vec::new(),
+            lod_distance,
+            tile_span: (tile_size - 1) as f32,
+            height_range: height_range(terrain),
+            skirt_depth: skirt_depth(terrain),
+            streamed: Vec::new(),
+            layers: HashMap::new(),
+            free_layers: (0..num_layers).rev().collect(),
+            unbaked: Vec::new(),
+            bake_jobs,
+            tile_heights,
             terrain_data,
+            terrain_size: terrain.size,
+            biome_map,
+            biomes: biome_texels(terrain),
+            biomes_changed: true,
             binding,
+            bake_binding,
+        }
+    }
+
+    pub fn stats(&self) -> TerrainStats {
+        TerrainStats {
+            streamed: self.streamed.len() as _,
+            visible: self.tiles.len(),
         }
     }
+
+    /// Sets the tiles that are in range of the camera, nearest first.
+    /// New tiles are given a layer of the baked maps and queued for
+    /// baking. These get culled and turned into instances by
+    /// [`Self::cull`].
+    pub fn set_streamed(&mut self, terrain: &Terrain, tiles: Vec<(u32, u32)>) {
+        let free_layers = &mut self.free_layers;
+        self.layers.retain(|id, tile| {
+            let keep = tiles.contains(id);
+            if !keep {
+                free_layers.push(tile.layer);
+            }
+            keep
+        });
+        self.unbaked
+            .retain(|tile| self.layers.contains_key(&tile.id));
+
+        let mut dropped = 0;
+        for &id in &tiles {
+            if self.layers.contains_key(&id) {
+                continue;
+            }
+            match self.free_layers.pop() {
+                Some(layer) => {
+                    let heights = terrain.tile_heights(id);
+                    let (min_height, max_height) = self.tile_height_range(heights);
+                    self.layers.insert(
+                        id,
+                        TileLayer {
+                            layer,
+                            baked: false,
+                            min_height,
+                            max_height,
+                        },
+                    );
+                    self.unbaked.push(UnbakedTile {
+                        id,
+                        heights: heights.cloned(),
+                    });
+                }
+                None => dropped += 1,
+            }
+        }
+        if dropped > 0 {
+            log::warn!("Out of baked terrain layers, {dropped} tiles won't be drawn");
+        }
+
+        self.streamed = tiles;
+    }
+
+    /// Uploads the terrain's biome heights, noise and seed, then re-bakes
+    /// every streamed tile with them.
+    pub fn update_terrain_data(&mut self, queue: &wgpu::Queue, terrain: &Terrain) {
+        self.terrain_data
+            .update(queue, |data| data[0].set_terrain(terrain));
+        self.height_range = height_range(terrain);
+        self.skirt_depth = skirt_depth(terrain);
+
+        let streamed = self.streamed.clone();
+        self.invalidate(terrain, &streamed);
+    }
+
+    /// Re-bakes tiles whose heights or biomes have changed, ahead of any
+    /// other tiles waiting to be baked. Tiles that aren't streamed in are
+    /// skipped.
+    pub fn invalidate(&mut self, terrain: &Terrain, ids: &[(u32, u32)]) {
+        // A tile's biome weights reach the centres of its neighbours, so
+        // those change too
+        let mut ids = ids.to_vec();
+        let biomes = biome_texels(terrain);
+        let size = self.terrain_size;
+        for (i, (old, new)) in self.biomes.iter().zip(&biomes).enumerate() {
+            if old == new {
+                continue;
+            }
+            let (x, z) = (i as u32 % size, i as u32 / size);
+            for nz in z.saturating_sub(1)..=(z + 1).min(size - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(size - 1) {
+                    if !ids.contains(&(nx, nz)) {
+                        ids.push((nx, nz));
+                    }
+                }
+            }
+        }
+        if biomes != self.biomes {
+            self.biomes = biomes;
+            self.biomes_changed = true;
+        }
+
+        for &id in ids.iter().rev() {
+            let heights = terrain.tile_heights(id);
+            let (min_height, max_height) = self.tile_height_range(heights);
+            let Some(tile) = self.layers.get_mut(&id) else {
+                continue;
+            };
+            // Keep drawing what was baked before until the new bake
+            // replaces it
+            tile.min_height = tile.min_height.min(min_height);
+            tile.max_height = tile.max_height.max(max_height);
+
+            self.unbaked.retain(|tile| tile.id != id);
+            self.unbaked.insert(
+                0,
+                UnbakedTile {
+                    id,
+                    heights: heights.cloned(),
+                },
+            );
+        }
+    }
+
+    /// Finds which baked tiles the camera can see and what level of
+    /// detail they need. The instance buffer is only written when that
+    /// changes.
+    pub fn cull(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, camera: &impl Camera) {
+        let frustum = camera.frustum();
+        let view_pos = camera.view_pos();
+        let viewer = glam::vec2(view_pos.x, view_pos.z);
+
+        let mut tiles = Vec::with_capacity(self.streamed.len());
+        for id in &self.streamed {
+            let tile = match self.layers.get(id) {
+                Some(tile) if tile.baked => tile,
+                _ => continue,
+            };
+
+            let position = glam::vec2(id.0 as f32, id.1 as f32) * self.tile_span;
+            let closest = viewer.clamp(position, position + self.tile_span);
+            let lod = self.lod_for_distance(viewer.distance(closest));
+
+            let (min, max) = self.tile_bounds(position, tile, lod);
+            if !frustum.intersects_aabb(min, max) {
+                continue;
+            }
+
+            tiles.push(TileInstance {
+                position,
+                lod,
+                layer: tile.layer,
+            });
+        }
+
+        self.set_tiles(device, queue, tiles);
+    }
+
+    /// Queues up the next batch of tiles for [`TerrainPipeline::bake`].
+    /// They are treated as baked from here on.
+    pub fn prepare_bake(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.bake_jobs.clear();
+        self.tile_heights.clear();
+
+        if self.biomes_changed {
+            queue.write_texture(
+                self.biome_map.as_image_copy(),
+                bytemuck::cast_slice(&self.biomes),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.terrain_size * size_of::<glam::Vec4>() as u32),
+                    rows_per_image: None,
+                },
+                self.biome_map.size(),
+            );
+            self.biomes_changed = false;
+        }
+
+        // Tiles with height samples are limited by the space for their
+        // samples, so the batch may end early
+        let mut height_bakes = 0;
+        let count = self
+            .unbaked
+            .iter()
+            .take(MAX_BAKES_PER_FRAME as _)
+            .take_while(|tile| {
+                height_bakes += tile.heights.is_some() as u32;
+                height_bakes <= MAX_HEIGHT_BAKES_PER_FRAME
+            })
+            .count();
+
+        let mut jobs = self.bake_jobs.batch(device, queue);
+        let mut samples = self.tile_heights.batch(device, queue);
+        let mut heights_offset = 0;
+        for unbaked in self.unbaked.drain(..count) {
+            let tile = self.layers.get_mut(&unbaked.id).unwrap();
+            tile.baked = true;
+
+            let mut job = BakeJob {
+                tile_offset: glam::vec2(unbaked.id.0 as f32, unbaked.id.1 as f32) * self.tile_span,
+                layer: tile.layer,
+                height_mode: BakeJob::HEIGHTS_NONE,
+                heights_offset,
+                _padding: 0,
+            };
+            if let Some(heights) = unbaked.heights {
+                job.height_mode = match heights.mode {
+                    HeightMode::Offset => BakeJob::HEIGHTS_OFFSET,
+                    HeightMode::Replace => BakeJob::HEIGHTS_REPLACE,
+                };
+                heights_offset += heights.samples.len() as u32;
+                for sample in heights.samples {
+                    samples.push(sample);
+                }
+            }
+            jobs.push(job);
+        }
+    }
+
+    /// Bounds the height of a tile, given the samples it is baked with.
+    fn tile_height_range(&self, heights: Option<&TileHeights>) -> (f32, f32) {
+        let Some(heights) = heights else {
+            return (self.height_range.start, self.height_range.end);
+        };
+
+        let (min, max) = heights
+            .samples
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &h| {
+                (min.min(h), max.max(h))
+            });
+        match heights.mode {
+            HeightMode::Offset => (self.height_range.start + min, self.height_range.end + max),
+            HeightMode::Replace => (min, max),
+        }
+    }
+
+    /// Box around everything drawn for a tile at `position`. Skirts hang
+    /// below the surface, and morphing slides vertices along the grid by
+    /// up to twice the level's stride. Morphed heights are still sampled
+    /// from the tile, so they stay within its height range.
+    fn tile_bounds(
+        &self,
+        position: glam::Vec2,
+        tile: &TileLayer,
+        lod: u32,
+    ) -> (glam::Vec3, glam::Vec3) {
+        let morph = ((2 << lod) - 1) as f32;
+        let min = position - morph;
+        let max = position + self.tile_span + morph;
+        (
+            glam::vec3(min.x, tile.min_height - self.skirt_depth, min.y),
+            glam::vec3(max.x, tile.max_height, max.y),
+        )
+    }
+
+    /// Picks the level of detail for a tile whose closest point is
+    /// `distance` away from the camera. Level `n` is used out to
+    /// `lod_distance * 2^n`.
+    pub fn lod_for_distance(&self, distance: f32) -> u32 {
+        let max_lod = self.lods.len() as u32 - 1;
+        if distance < self.lod_distance {
+            return 0;
+        }
+        ((distance / self.lod_distance).log2().floor() as u32 + 1).min(max_lod)
+    }
+
+    /// Replaces the tiles to draw. Tiles are grouped by level of detail so
+    /// that each level is a single draw call.
+    fn set_tiles(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mut tiles: Vec<TileInstance>,
+    ) {
+        tiles.sort_by_key(|tile| tile.lod);
+        if tiles == self.tiles.data() {
+            return;
+        }
+
+        self.lod_ranges.clear();
+        let mut start = 0;
+        for lod in 0..self.lods.len() as u32 {
+            let count = tiles[start as usize..]
+                .iter()
+                .take_while(|tile| tile.lod == lod)
+                .count() as u32;
+            self.lod_ranges.push(start..start + count);
+            start += count;
+        }
+
+        self.tiles.clear();
+        let mut batch = self.tiles.batch(device, queue);
+        for tile in tiles {
+            batch.push(tile);
+        }
+    }
+}
+
+/// How far skirts hang below the edges of tiles.
+fn skirt_depth(terrain: &Terrain) -> f32 {
+    SKIRT_DEPTH
+        * terrain
+            .mountain_height
+            .max(terrain.dune_height)
+            .max(terrain.spire_height)
+}
+
+/// Bounds the procedural height of the terrain.
+fn height_range(terrain: &Terrain) -> Range<f32> {
+    // Biome weights sum to one, so the tallest a point can be is the sum
+    // of the tallest each biome can be. Spires stand on top of dunes
+    let (fbm_min, fbm_max) = terrain.noise.mountains.range();
+    let low = (fbm_min * terrain.mountain_height).min(0.0);
+    let high =
+        fbm_max.max(1.0) * terrain.mountain_height + terrain.dune_height + terrain.spire_height;
+    low..high
+}
+
+/// One texel per tile of the biome map, row by row.
+fn biome_texels(terrain: &Terrain) -> Vec<glam::Vec4> {
+    (0..terrain.size * terrain.size)
+        .map(|i| {
+            let id = (i % terrain.size, i / terrain.size);
+            let biome = terrain.tile(id).map(|tile| tile.biome).unwrap_or_default();
+            biome.to_vec3().extend(0.0)
+        })
+        .collect()
+}
+
+/// Builds the index buffer for one level of detail. Level `lod` uses every
+/// `2^lod`th vertex of the full resolution grid, plus the far edge so tiles
+/// that don't divide evenly still meet their neighbours.
+///
+/// Skirt vertices are addressed as `tile_size * tile_size + i`, where `i`
+/// is the edge vertex the skirt hangs from.
+fn lod_indices(tile_size: u32, lod: u32) -> Vec<u32> {
+    let n = tile_size;
+    let mut lines = (0..n - 1).step_by(1 << lod).collect::<Vec<_>>();
+    lines.push(n - 1);
+
+    let mut index_data = Vec::new();
+    for z in lines.windows(2) {
+        for x in lines.windows(2) {
+            let i00 = x[0] + z[0] * n;
+            let i10 = x[1] + z[0] * n;
+            let i01 = x[0] + z[1] * n;
+            let i11 = x[1] + z[1] * n;
+            index_data.extend_from_slice(&[i00, i11, i10, i00, i01, i11]);
+        }
+    }
+
+    let skirt = n * n;
+    let edges = [
+        lines.clone(),
+        lines.iter().map(|&x| x + (n - 1) * n).collect(),
+        lines.iter().map(|&z| z * n).collect(),
+        lines.iter().map(|&z| n - 1 + z * n).collect(),
+    ];
+    for edge in &edges {
+        for pair in edge.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            // Skirts can be seen from either side, so emit both windings
+            index_data.extend_from_slice(&[a, b, b + skirt, a, b + skirt, a + skirt]);
+            index_data.extend_from_slice(&[a, b + skirt, b, a, a + skirt, b + skirt]);
+        }
+    }
+
+    index_data
 }
 
 pub struct TerrainPipeline {
     triplanar_pipeline: wgpu::RenderPipeline,
     debug_pipeline: wgpu::RenderPipeline,
+    bake_pipeline: wgpu::ComputePipeline,
 }
 
 impl TerrainPipeline {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         app: &AppController,
         device: &wgpu::Device,
-        uniform_binder: &UniformBinder<TerrainData>,
+        terrain_binder: &TerrainBinder,
+        bake_binder: &TerrainBakeBinder,
         camera_binder: &CameraBinder,
-        texture_binder: &SampledTextureArrayBinder,
+        material_binder: &MaterialBinder,
+        lighting_binder: &UniformBinder<LightingData>,
         surface_format: wgpu::TextureFormat,
         depth_format: wgpu::TextureFormat,
     ) -> anyhow::Result<Self> {
         let triplanar_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             bind_group_layouts: &[
-                uniform_binder.layout(),
+                terrain_binder.layout(),
                 camera_binder.layout(),
-                texture_binder.layout(),
+                material_binder.layout(),
+                lighting_binder.layout(),
             ],
             ..Default::default()
         });
 
         let debug_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            bind_group_layouts: &[uniform_binder.layout(), camera_binder.layout()],
+            bind_group_layouts: &[terrain_binder.layout(), camera_binder.layout()],
             ..Default::default()
         });
 
+        let bake_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[bake_binder.layout()],
+            ..Default::default()
+        });
+
+        let common = app.load_string("shaders/terrain_common.wgsl").await?;
+
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("shaders/terrain.wgsl"),
-            source: wgpu::ShaderSource::Wgsl(app.load_string("shaders/terrain.wgsl").await?.into()),
+            source: wgpu::ShaderSource::Wgsl(
+                format!(
+                    "{common}\n{}",
+                    app.load_string("shaders/terrain.wgsl").await?
+                )
+                .into(),
+            ),
+        });
+
+        let bake_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shaders/terrain_bake.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                format!(
+                    "{common}\n{}",
+                    app.load_string("shaders/terrain_bake.wgsl").await?
+                )
+                .into(),
+            ),
         });
 
         log::debug!("triplanar_pipeline");
@@ -169,17 +794,47 @@ impl TerrainPipeline {
             })
             .build(device)?;
 
+        let bake_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("bake_pipeline"),
+            layout: Some(&bake_layout),
+            module: &bake_shader,
+            entry_point: Some("bake_tile"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
         Ok(Self {
             triplanar_pipeline,
             debug_pipeline,
+            bake_pipeline,
         })
     }
 
+    /// Bakes the tiles queued by [`TerrainBuffer::prepare_bake`].
+    pub fn bake(&self, encoder: &mut wgpu::CommandEncoder, buffer: &TerrainBuffer) {
+        let num_jobs = buffer.bake_jobs.len();
+        if num_jobs == 0 {
+            return;
+        }
+
+        let tile_size = buffer.tile_span as u32 + 1;
+        let workgroups = tile_size.div_ceil(BAKE_WORKGROUP_SIZE);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("bake_pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.bake_pipeline);
+        pass.set_bind_group(0, buffer.bake_binding.bind_group(), &[]);
+        pass.dispatch_workgroups(workgroups, workgroups, num_jobs);
+    }
+
     pub fn draw<'a, 'b: 'a>(
         &'a self,
         pass: &'a mut wgpu::RenderPass<'b>,
         camera: &CameraBinding,
-        textures: &SampledTextureArrayBinding,
+        materials: &MaterialBinding,
+        lighting: &UniformBinding<LightingData>,
         buffer: &'a TerrainBuffer,
     ) {
         if buffer.tiles.len() == 0 {
@@ -189,10 +844,9 @@ impl TerrainPipeline {
         pass.set_pipeline(&self.triplanar_pipeline);
         pass.set_bind_group(0, buffer.binding.bind_group(), &[]);
         pass.set_bind_group(1, camera.bind_group(), &[]);
-        pass.set_bind_group(2, textures.bind_group(), &[]);
-        pass.set_index_buffer(buffer.indices.slice(), wgpu::IndexFormat::Uint32);
-        pass.set_vertex_buffer(0, buffer.tiles.slice());
-        pass.draw_indexed(0..buffer.indices.len(), 0, 0..buffer.tiles.len());
+        pass.set_bind_group(2, materials.bind_group(), &[]);
+        pass.set_bind_group(3, lighting.bind_group(), &[]);
+        Self::draw_lods(pass, buffer);
     }
 
     pub fn debug<'a, 'b: 'a>(
@@ -208,8 +862,17 @@ impl TerrainPipeline {
         pass.set_pipeline(&self.debug_pipeline);
         pass.set_bind_group(0, buffer.binding.bind_group(), &[]);
         pass.set_bind_group(1, camera.bind_group(), &[]);
-        pass.set_index_buffer(buffer.indices.slice(), wgpu::IndexFormat::Uint32);
+        Self::draw_lods(pass, buffer);
+    }
+
+    fn draw_lods(pass: &mut wgpu::RenderPass<'_>, buffer: &TerrainBuffer) {
         pass.set_vertex_buffer(0, buffer.tiles.slice());
-        pass.draw_indexed(0..buffer.indices.len(), 0, 0..buffer.tiles.len());
+        for (indices, range) in buffer.lods.iter().zip(&buffer.lod_ranges) {
+            if range.is_empty() {
+                continue;
+            }
+            pass.set_index_buffer(indices.slice(), wgpu::IndexFormat::Uint32);
+            pass.draw_indexed(0..indices.len(), 0, range.clone());
+        }
     }
 }
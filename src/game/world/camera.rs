@@ -1,5 +1,7 @@
 use winit::{dpi::PhysicalPosition, event::MouseScrollDelta, keyboard::KeyCode};
 
+use crate::game::world::terrain::Terrain;
+
 pub trait Camera {
     fn view_pos(&self) -> glam::Vec3;
     fn view(&self) -> glam::Mat4;
@@ -7,6 +9,44 @@ pub trait Camera {
     fn view_proj(&self) -> glam::Mat4 {
         self.proj() * self.view()
     }
+    fn frustum(&self) -> Frustum {
+        Frustum::from_view_proj(self.view_proj())
+    }
+}
+
+/// The six planes bounding what a camera can see. Each plane is stored as
+/// `(normal, distance)` with the normal pointing into the frustum.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix with a `0..1`
+    /// depth range.
+    pub fn from_view_proj(view_proj: glam::Mat4) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().length());
+
+        Self { planes }
+    }
+
+    /// Returns `false` only if the box is entirely outside the frustum.
+    /// Boxes near a corner of the frustum may be reported as visible
+    /// when they aren't.
+    pub fn intersects_aabb(&self, min: glam::Vec3, max: glam::Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            // The corner of the box furthest along the plane normal
+            let corner = glam::Vec3::select(normal.cmpge(glam::Vec3::ZERO), max, min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
 }
 
 pub struct Camera2d {
@@ -18,7 +58,7 @@ impl Camera2d {
     pub fn new(width: f32, height: f32) -> Self {
         Self { width, height }
     }
-    
+
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
         self.width = width as f32;
         self.height = height as f32;
@@ -41,6 +81,24 @@ impl Camera for Camera2d {
 
 const SAFE_FRAC_PI_2: f32 = std::f32::consts::FRAC_PI_2 - 0.0001;
 
+/// Height of the camera above the ground when walking.
+const EYE_HEIGHT: f32 = 1.8;
+const GRAVITY: f32 = 25.0;
+const JUMP_SPEED: f32 = 9.0;
+/// Steepest slope, in radians, that can be walked up.
+const MAX_WALK_SLOPE: f32 = 0.8;
+/// How far below the feet the ground can drop before we stop
+/// snapping to it and start falling.
+const STEP_DOWN: f32 = 0.5;
+/// Longest time step the walk physics will take in one go.
+const MAX_WALK_DT: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementMode {
+    Fly,
+    Walk,
+}
+
 #[derive(Debug)]
 pub struct PerspectiveCamera {
     pub position: glam::Vec3,
@@ -77,6 +135,16 @@ impl PerspectiveCamera {
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
+
+    /// Origin and direction of the ray through `cursor`, in pixels from
+    /// the top left of a `size` pixel window.
+    pub fn screen_ray(&self, cursor: glam::Vec2, size: glam::Vec2) -> (glam::Vec3, glam::Vec3) {
+        let ndc = (cursor / size * 2.0 - 1.0) * glam::vec2(1.0, -1.0);
+        let inverse = self.view_proj().inverse();
+        let near = inverse.project_point3(ndc.extend(0.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        (near, (far - near).normalize())
+    }
 }
 
 impl Camera for PerspectiveCamera {
@@ -112,12 +180,16 @@ pub struct CameraController {
     rotate_vertical: f32,
     scroll: f32,
     speed: f32,
+    walk_speed: f32,
     sensitivity: f32,
     sprint_pressed: bool,
+    mode: MovementMode,
+    vertical_velocity: f32,
+    grounded: bool,
 }
 
 impl CameraController {
-    pub fn new(speed: f32, sensitivity: f32) -> Self {
+    pub fn new(speed: f32, walk_speed: f32, sensitivity: f32) -> Self {
         Self {
             amount_left: 0.0,
             amount_right: 0.0,
@@ -129,11 +201,28 @@ impl CameraController {
             rotate_vertical: 0.0,
             scroll: 0.0,
             speed,
+            walk_speed,
             sensitivity,
             sprint_pressed: false,
+            mode: MovementMode::Fly,
+            vertical_velocity: 0.0,
+            grounded: false,
         }
     }
 
+    pub fn mode(&self) -> MovementMode {
+        self.mode
+    }
+
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            MovementMode::Fly => MovementMode::Walk,
+            MovementMode::Walk => MovementMode::Fly,
+        };
+        self.vertical_velocity = 0.0;
+        self.grounded = false;
+    }
+
     pub fn process_keyboard(&mut self, key: KeyCode, pressed: bool) -> bool {
         let amount = if pressed { 1.0 } else { 0.0 };
         match key {
@@ -165,6 +254,12 @@ impl CameraController {
                 self.sprint_pressed = pressed;
                 true
             }
+            KeyCode::KeyG => {
+                if pressed {
+                    self.toggle_mode();
+                }
+                true
+            }
             _ => false,
         }
     }
@@ -183,25 +278,19 @@ impl CameraController {
         };
     }
 
-    pub fn update_camera(&mut self, camera: &mut PerspectiveCamera, dt: web_time::Duration) {
+    pub fn update_camera(
+        &mut self,
+        camera: &mut PerspectiveCamera,
+        terrain: &Terrain,
+        dt: web_time::Duration,
+    ) {
         let dt = dt.as_secs_f32();
 
-        let mut speed = self.speed;
-        if self.sprint_pressed {
-            speed *= 4.0;
+        match self.mode {
+            MovementMode::Fly => self.fly(camera, dt),
+            MovementMode::Walk => self.walk(camera, terrain, dt.min(MAX_WALK_DT)),
         }
 
-        // Move forward/backward and left/right
-        let (yaw_sin, yaw_cos) = camera.yaw.sin_cos();
-        let forward = glam::Vec3::new(yaw_cos, 0.0, yaw_sin).normalize();
-        let right = glam::Vec3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * speed * dt;
-
-        // Move up/down. Since we don't use roll, we can just
-        // modify the y coordinate directly.
-        camera.position.y += (self.amount_up - self.amount_down) * speed * dt;
-
         // Rotate
         camera.yaw += self.rotate_horizontal * self.sensitivity * dt;
         camera.pitch += -self.rotate_vertical * self.sensitivity * dt;
@@ -219,4 +308,217 @@ impl CameraController {
             camera.pitch = SAFE_FRAC_PI_2;
         }
     }
+
+    fn fly(&mut self, camera: &mut PerspectiveCamera, dt: f32) {
+        let mut speed = self.speed;
+        if self.sprint_pressed {
+            speed *= 4.0;
+        }
+
+        // Move forward/backward and left/right
+        let (forward, right) = Self::ground_axes(camera);
+        camera.position += forward * (self.amount_forward - self.amount_backward) * speed * dt;
+        camera.position += right * (self.amount_right - self.amount_left) * speed * dt;
+
+        // Move up/down. Since we don't use roll, we can just
+        // modify the y coordinate directly.
+        camera.position.y += (self.amount_up - self.amount_down) * speed * dt;
+    }
+
+    fn walk(&mut self, camera: &mut PerspectiveCamera, terrain: &Terrain, dt: f32) {
+        let mut speed = self.walk_speed;
+        if self.sprint_pressed {
+            speed *= 2.0;
+        }
+
+        let (forward, right) = Self::ground_axes(camera);
+        let direction = (forward * (self.amount_forward - self.amount_backward)
+            + right * (self.amount_right - self.amount_left))
+            .normalize_or_zero();
+        let target = camera.position + direction * speed * dt;
+
+        // Only refuse a step if it takes us up something too steep,
+        // so we can always walk back down.
+        let ground_here = terrain.height_at(camera.position.x, camera.position.z);
+        let ground_there = terrain.height_at(target.x, target.z);
+        let too_steep =
+            ground_there > ground_here && terrain.slope_at(target.x, target.z) > MAX_WALK_SLOPE;
+        if !too_steep {
+            camera.position.x = target.x;
+            camera.position.z = target.z;
+        }
+
+        if self.grounded && self.amount_up > 0.0 {
+            self.vertical_velocity = JUMP_SPEED;
+            self.grounded = false;
+        }
+
+        self.vertical_velocity -= GRAVITY * dt;
+        camera.position.y += self.vertical_velocity * dt;
+
+        let ground = terrain.height_at(camera.position.x, camera.position.z);
+        let feet = camera.position.y - EYE_HEIGHT;
+        let snap = self.grounded && self.vertical_velocity <= 0.0 && feet - ground < STEP_DOWN;
+        if feet <= ground || snap {
+            camera.position.y = ground + EYE_HEIGHT;
+            self.vertical_velocity = 0.0;
+            self.grounded = true;
+        } else {
+            self.grounded = false;
+        }
+    }
+
+    /// Forward and right vectors flattened onto the xz plane.
+    fn ground_axes(camera: &PerspectiveCamera) -> (glam::Vec3, glam::Vec3) {
+        let (yaw_sin, yaw_cos) = camera.yaw.sin_cos();
+        let forward = glam::Vec3::new(yaw_cos, 0.0, yaw_sin).normalize();
+        let right = glam::Vec3::new(-yaw_sin, 0.0, yaw_cos).normalize();
+        (forward, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::terrain::{biome::Biome, heightfield::tests::fixture};
+
+    /// A controller in walk mode and a camera at `position` looking along
+    /// `yaw`.
+    fn walker(position: glam::Vec3, yaw: f32) -> (CameraController, PerspectiveCamera) {
+        let mut controller = CameraController::new(20.0, 5.0, 1.0);
+        controller.toggle_mode();
+        let camera = PerspectiveCamera::new(position, yaw, 0.0, 16, 9, 45.0, 0.1, 100.0);
+        (controller, camera)
+    }
+
+    /// Runs `frames` frames at 60 fps, checking the walker stays on top of
+    /// the ground throughout.
+    fn walk(
+        controller: &mut CameraController,
+        camera: &mut PerspectiveCamera,
+        terrain: &Terrain,
+        frames: u32,
+    ) {
+        for _ in 0..frames {
+            controller.update_camera(
+                camera,
+                terrain,
+                web_time::Duration::from_secs_f32(1.0 / 60.0),
+            );
+            let ground = terrain.height_at(camera.position.x, camera.position.z);
+            assert!(
+                camera.position.y - EYE_HEIGHT >= ground - 1e-4,
+                "feet at {} below the ground at {ground}",
+                camera.position.y - EYE_HEIGHT
+            );
+        }
+    }
+
+    #[test]
+    fn walker_rests_at_eye_height() {
+        let terrain = fixture(2, 33, Biome::Dunes, |_, _| 5.0);
+        let (mut controller, mut camera) = walker(glam::vec3(32.0, 20.0, 32.0), 0.0);
+
+        walk(&mut controller, &mut camera, &terrain, 120);
+        assert!((camera.position.y - (5.0 + EYE_HEIGHT)).abs() < 1e-4);
+        assert!(controller.grounded);
+    }
+
+    #[test]
+    fn jump_lands_again() {
+        let terrain = fixture(2, 33, Biome::Dunes, |_, _| 5.0);
+        let (mut controller, mut camera) = walker(glam::vec3(32.0, 5.0 + EYE_HEIGHT, 32.0), 0.0);
+        walk(&mut controller, &mut camera, &terrain, 1);
+
+        controller.process_keyboard(KeyCode::Space, true);
+        walk(&mut controller, &mut camera, &terrain, 1);
+        controller.process_keyboard(KeyCode::Space, false);
+        walk(&mut controller, &mut camera, &terrain, 10);
+        assert!(camera.position.y > 6.0 + EYE_HEIGHT);
+        assert!(!controller.grounded);
+
+        walk(&mut controller, &mut camera, &terrain, 60);
+        assert!((camera.position.y - (5.0 + EYE_HEIGHT)).abs() < 1e-4);
+        assert!(controller.grounded);
+    }
+
+    #[test]
+    fn steep_slopes_block_walker() {
+        // Rises 2 for every 1 across, steeper than MAX_WALK_SLOPE
+        let steep = fixture(2, 33, Biome::Mountains, |x, _| x as f32 * 2.0);
+        let start = glam::vec3(8.0, 16.0 + EYE_HEIGHT, 32.0);
+
+        let (mut controller, mut camera) = walker(start, 0.0);
+        controller.process_keyboard(KeyCode::KeyW, true);
+        walk(&mut controller, &mut camera, &steep, 120);
+        assert!(
+            camera.position.x < start.x + 0.1,
+            "climbed to {}",
+            camera.position
+        );
+
+        // Walking back down is always allowed
+        let (mut controller, mut camera) = walker(start, std::f32::consts::PI);
+        controller.process_keyboard(KeyCode::KeyW, true);
+        walk(&mut controller, &mut camera, &steep, 60);
+        assert!(
+            camera.position.x < start.x - 4.0,
+            "stuck at {}",
+            camera.position
+        );
+    }
+
+    #[test]
+    fn gentle_slopes_can_be_climbed() {
+        let ramp = fixture(2, 33, Biome::Mountains, |x, _| x as f32 * 0.5);
+        let start = glam::vec3(8.0, 4.0 + EYE_HEIGHT, 32.0);
+
+        let (mut controller, mut camera) = walker(start, 0.0);
+        controller.process_keyboard(KeyCode::KeyW, true);
+        walk(&mut controller, &mut camera, &ramp, 120);
+        assert!(
+            camera.position.x > start.x + 8.0,
+            "stuck at {}",
+            camera.position
+        );
+        let ground = ramp.height_at(camera.position.x, camera.position.z);
+        assert!((camera.position.y - (ground + EYE_HEIGHT)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn extracts_normalized_planes() {
+        let proj = glam::Mat4::orthographic_rh(-1.0, 1.0, -2.0, 2.0, 0.0, 10.0);
+        let frustum = Frustum::from_view_proj(proj);
+        let expected = [
+            glam::vec4(1.0, 0.0, 0.0, 1.0),
+            glam::vec4(-1.0, 0.0, 0.0, 1.0),
+            glam::vec4(0.0, 1.0, 0.0, 2.0),
+            glam::vec4(0.0, -1.0, 0.0, 2.0),
+            glam::vec4(0.0, 0.0, -1.0, 0.0),
+            glam::vec4(0.0, 0.0, 1.0, 10.0),
+        ];
+        for (plane, expected) in frustum.planes.iter().zip(expected) {
+            assert!(plane.abs_diff_eq(expected, 1e-5), "{plane} != {expected}");
+        }
+    }
+
+    #[test]
+    fn rejects_boxes_outside() {
+        // Looking down -z from the origin
+        let proj = glam::Mat4::perspective_rh(90f32.to_radians(), 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_view_proj(proj);
+        let visible = |center: glam::Vec3| {
+            frustum.intersects_aabb(center - glam::Vec3::ONE, center + glam::Vec3::ONE)
+        };
+
+        assert!(visible(glam::vec3(0.0, 0.0, -10.0)));
+        // Straddling the near plane and a side
+        assert!(visible(glam::Vec3::ZERO));
+        assert!(visible(glam::vec3(10.5, 0.0, -10.0)));
+
+        assert!(!visible(glam::vec3(0.0, 0.0, 10.0)));
+        assert!(!visible(glam::vec3(0.0, 0.0, -102.0)));
+        assert!(!visible(glam::vec3(-13.0, 0.0, -10.0)));
+        assert!(!visible(glam::vec3(0.0, 13.0, -10.0)));
+    }
 }
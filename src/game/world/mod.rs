@@ -1,21 +1,36 @@
+use std::path::Path;
+
+use web_time::{Instant, SystemTime};
+
 use crate::{
     app::AppController,
     game::world::{
         camera::{Camera2d, PerspectiveCamera},
-        terrain::Terrain,
+        terrain::{Terrain, TerrainHit, file, import},
     },
 };
 
 pub mod camera;
+pub mod sun;
 pub mod terrain;
 
+/// Where the terrain is saved, relative to the res folder.
+pub(crate) const TERRAIN_PATH: &str = "terrains/default.dirt";
+/// Where the terrain was saved as JSON, before the binary format.
+const LEGACY_TERRAIN_PATH: &str = "terrains/default.json";
+
 pub struct World {
     pub ui_camera: Camera2d,
     pub player_camera: PerspectiveCamera,
     pub terrain: Terrain,
+    /// Why files couldn't be loaded, to show the player.
+    pub(crate) load_errors: Vec<String>,
 }
 
 impl World {
+    /// Loads the saved terrain, or imports or generates one as asked on
+    /// the command line. `seed` is used if a terrain has to be generated
+    /// and none was given on the command line.
     pub(crate) async fn new(
         app: &AppController,
         width: u32,
@@ -23,6 +38,7 @@ impl World {
         terrain_size: u32,
         tile_size: u32,
         max_height: f32,
+        seed: Option<u32>,
     ) -> Self {
         let ui_camera = Camera2d::new(width as f32, height as f32);
 
@@ -38,22 +54,128 @@ impl World {
             1000.0,
         );
 
-        let terrain = match app.load_string("terrains/default.json").await {
-            Ok(json) => serde_json::from_str(&json).unwrap(),
-            Err(_) => {
-                Terrain::generate(terrain_size, tile_size, max_height, max_height, max_height)
-            }
+        let mut load_errors = Vec::new();
+        let imported = match &app.args().import_heightmap {
+            Some(path) => Self::import_heightmap(app, path, tile_size, &mut load_errors).await,
+            None => None,
         };
+        let generate = |seed: Option<u32>| {
+            let seed = seed.unwrap_or_else(random_seed);
+            log::info!("Generating terrain with seed {seed}");
+            Terrain::generate(
+                terrain_size,
+                tile_size,
+                max_height,
+                max_height,
+                max_height,
+                seed,
+            )
+        };
+        let terrain = match (imported, app.args().seed) {
+            (Some(terrain), _) => terrain,
+            (None, Some(seed)) => generate(Some(seed)),
+            (None, None) => match Self::load_terrain(app, &mut load_errors).await {
+                Some(terrain) => terrain,
+                None => generate(seed),
+            },
+        };
+
+        let expected = (terrain.tile_size * terrain.tile_size) as usize;
+        for tile in &terrain.tiles {
+            if let Some(heights) = &tile.heights
+                && heights.samples.len() != expected
+            {
+                log::warn!(
+                    "Ignoring heights of tile {:?}: expected {expected} samples, found {}",
+                    tile.id,
+                    heights.samples.len(),
+                );
+            }
+        }
 
         Self {
             ui_camera,
             player_camera,
             terrain,
+            load_errors,
         }
     }
 
+    /// Loads the saved terrain, falling back to the legacy JSON file. A
+    /// file that can't be loaded is reported in `load_errors` and kept.
+    async fn load_terrain(app: &AppController, load_errors: &mut Vec<String>) -> Option<Terrain> {
+        let timer = Instant::now();
+        let (path, data) = match app.load_binary(TERRAIN_PATH).await {
+            Ok(data) => (TERRAIN_PATH, data),
+            Err(_) => (
+                LEGACY_TERRAIN_PATH,
+                app.load_binary(LEGACY_TERRAIN_PATH).await.ok()?,
+            ),
+        };
+
+        match file::decode(&data) {
+            Ok(terrain) => {
+                log::info!(
+                    "Loaded terrain from {path} ({} bytes) in {:?}",
+                    data.len(),
+                    timer.elapsed()
+                );
+                Some(terrain)
+            }
+            Err(e) => {
+                load_errors.push(
+                    app.reject_file(path, data, &e, "Generating a new terrain")
+                        .await,
+                );
+                None
+            }
+        }
+    }
+
+    /// Imports the heightmap at `path`. A heightmap that can't be imported
+    /// is reported in `load_errors`.
+    async fn import_heightmap(
+        app: &AppController,
+        path: &Path,
+        tile_size: u32,
+        load_errors: &mut Vec<String>,
+    ) -> Option<Terrain> {
+        let result = async {
+            let data = app.load_binary(path).await?;
+            import::import_heightmap(&data, tile_size, &app.args().heightmap_options)
+        };
+        match result.await {
+            Ok(terrain) => Some(terrain),
+            Err(e) => {
+                log::error!("Could not import {}: {e:?}", path.display());
+                load_errors.push(format!(
+                    "Could not import {}: {e:#}\n  Keeping the terrain it would have replaced",
+                    path.display()
+                ));
+                None
+            }
+        }
+    }
+
+    /// The terrain under `cursor`, in pixels from the top left of a `size`
+    /// pixel window, as seen by the player camera.
+    pub fn pick(&self, cursor: glam::Vec2, size: glam::Vec2) -> Option<TerrainHit> {
+        let (origin, direction) = self.player_camera.screen_ray(cursor, size);
+        self.terrain
+            .pick(origin, direction, self.player_camera.zfar)
+    }
+
     pub(crate) fn resize(&mut self, width: u32, height: u32) {
         self.ui_camera.resize(width, height);
         self.player_camera.resize(width, height);
     }
 }
+
+/// A seed for terrains generated without one, from the current time.
+pub(crate) fn random_seed() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    (nanos ^ (nanos >> 32)) as u32
+}
@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+
+/// Hours in a day, which [`LightingOptions::time_of_day`] counts up to.
+pub const DAY_HOURS: f32 = 24.0;
+/// How much of the ambient light is left at night.
+const NIGHT_AMBIENT: f32 = 0.25;
+
+/// The sun and the sky lighting the terrain. The sun rises in the east
+/// (+x) at 6:00, is highest in the south (+z) at 12:00 and sets in the
+/// west at 18:00.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightingOptions {
+    /// Hours since midnight, from 0 up to 24.
+    pub time_of_day: f32,
+    /// Seconds a whole day takes to pass. Time stands still at 0.
+    pub day_length: f32,
+    /// Height of the sun at noon, in degrees above the horizon.
+    pub noon_elevation: f32,
+    pub sun_color: [f32; 3],
+    pub sun_intensity: f32,
+    /// Ambient light on surfaces facing straight up.
+    pub sky_color: [f32; 3],
+    /// Ambient light on surfaces facing straight down.
+    pub ground_color: [f32; 3],
+}
+
+impl Default for LightingOptions {
+    fn default() -> Self {
+        Self {
+            time_of_day: 10.0,
+            day_length: 1200.0,
+            noon_elevation: 60.0,
+            sun_color: [1.0, 0.96, 0.9],
+            sun_intensity: 1.0,
+            sky_color: [0.1, 0.11, 0.13],
+            ground_color: [0.06, 0.05, 0.04],
+        }
+    }
+}
+
+impl LightingOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (0.0..DAY_HOURS).contains(&self.time_of_day),
+            "time_of_day must be from 0 up to {DAY_HOURS}, not {}",
+            self.time_of_day
+        );
+        anyhow::ensure!(
+            (0.0..=90.0).contains(&self.noon_elevation),
+            "noon_elevation must be from 0 to 90 degrees, not {}",
+            self.noon_elevation
+        );
+        for (name, value) in [
+            ("day_length", self.day_length),
+            ("sun_intensity", self.sun_intensity),
+        ] {
+            anyhow::ensure!(
+                value.is_finite() && value >= 0.0,
+                "{name} can't be negative, not {value}"
+            );
+        }
+        for (name, color) in [
+            ("sun_color", self.sun_color),
+            ("sky_color", self.sky_color),
+            ("ground_color", self.ground_color),
+        ] {
+            anyhow::ensure!(
+                color.iter().all(|c| c.is_finite() && *c >= 0.0),
+                "{name} can't have negative components: {color:?}"
+            );
+        }
+        Ok(())
+    }
+
+    /// Moves the time of day on by `dt` seconds, wrapping around at
+    /// midnight.
+    pub fn advance(&mut self, dt: f32) {
+        if self.day_length > 0.0 {
+            let hours = self.time_of_day + dt / self.day_length * DAY_HOURS;
+            self.time_of_day = hours.rem_euclid(DAY_HOURS);
+        }
+    }
+
+    /// Unit vector pointing towards the sun, below the horizon at night.
+    pub fn sun_direction(&self) -> glam::Vec3 {
+        // Round a circle tilted towards the south, from the east horizon
+        // at sunrise
+        let angle = (self.time_of_day - 6.0) / DAY_HOURS * std::f32::consts::TAU;
+        let tilt = self.noon_elevation.to_radians();
+        glam::vec3(
+            angle.cos(),
+            angle.sin() * tilt.sin(),
+            angle.sin() * tilt.cos(),
+        )
+    }
+
+    /// How much of the sun's light reaches the ground, fading out as it
+    /// sets.
+    pub fn daylight(&self) -> f32 {
+        let height = self.sun_direction().y;
+        ((height + 0.05) / 0.15).clamp(0.0, 1.0)
+    }
+
+    /// Sun colour, dimmed by its intensity and the time of day.
+    pub fn sun_light(&self) -> glam::Vec3 {
+        glam::Vec3::from(self.sun_color) * self.sun_intensity * self.daylight()
+    }
+
+    /// How bright the ambient light is at this time of day.
+    pub fn ambient_scale(&self) -> f32 {
+        NIGHT_AMBIENT + (1.0 - NIGHT_AMBIENT) * self.daylight()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time_of_day: f32) -> LightingOptions {
+        LightingOptions {
+            time_of_day,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sun_crosses_the_sky() {
+        let sunrise = at(6.0).sun_direction();
+        assert!(sunrise.abs_diff_eq(glam::Vec3::X, 1e-5), "{sunrise}");
+        let sunset = at(18.0).sun_direction();
+        assert!(sunset.abs_diff_eq(-glam::Vec3::X, 1e-5), "{sunset}");
+
+        let noon = at(12.0).sun_direction();
+        let elevation = noon.y.asin().to_degrees();
+        assert!((elevation - 60.0).abs() < 1e-3, "{elevation}");
+        assert!(noon.z > 0.0 && noon.x.abs() < 1e-5);
+
+        let midnight = at(0.0);
+        assert!(midnight.sun_direction().y < 0.0);
+        assert_eq!(midnight.sun_light(), glam::Vec3::ZERO);
+        assert_eq!(at(12.0).daylight(), 1.0);
+    }
+
+    #[test]
+    fn time_wraps_at_midnight() {
+        let mut lighting = at(23.0);
+        lighting.advance(lighting.day_length / 12.0);
+        assert!((lighting.time_of_day - 1.0).abs() < 1e-3);
+
+        lighting.day_length = 0.0;
+        lighting.advance(100.0);
+        assert!((lighting.time_of_day - 1.0).abs() < 1e-3);
+    }
+}
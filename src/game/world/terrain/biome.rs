@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::world::terrain::{Terrain, noise};
+
+/// Width of the regions [`Terrain::place_biomes`] gives each biome, in
+/// tiles.
+const REGION_TILES: f32 = 4.0;
+/// How quickly a region's biome fades out away from its centre. Higher
+/// makes the borders between regions sharper.
+const REGION_FALLOFF: f32 = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Biome {
+    Mountains,
+    Dunes,
+    Spires,
+}
+
+impl Biome {
+    pub const ALL: [Self; 3] = [Self::Mountains, Self::Dunes, Self::Spires];
+}
+
+/// How much of each biome a tile is at its centre. Only the ratios
+/// between the weights matter, they don't need to add up to one. Between
+/// the centres of tiles the weights are interpolated.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiomeWeights {
+    pub mountains: f32,
+    pub dunes: f32,
+    pub spires: f32,
+}
+
+impl Default for BiomeWeights {
+    /// All mountains, like imported heightmaps.
+    fn default() -> Self {
+        Self::only(Biome::Mountains)
+    }
+}
+
+impl BiomeWeights {
+    pub fn only(biome: Biome) -> Self {
+        let mut v = glam::Vec3::ZERO;
+        v[biome as usize] = 1.0;
+        Self::from_vec3(v)
+    }
+
+    pub fn to_vec3(self) -> glam::Vec3 {
+        glam::vec3(self.mountains, self.dunes, self.spires)
+    }
+
+    pub fn from_vec3(v: glam::Vec3) -> Self {
+        Self {
+            mountains: v.x,
+            dunes: v.y,
+            spires: v.z,
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let v = self.to_vec3();
+        anyhow::ensure!(
+            v.is_finite() && v.min_element() >= 0.0 && v.element_sum() > 0.0,
+            "Biome weights must be positive numbers, and not all zero: {self:?}"
+        );
+        Ok(())
+    }
+}
+
+impl Terrain {
+    /// Biome weights at `p`, interpolated between the centres of the four
+    /// nearest tiles the same way `biome_blend` in the shader does. Off the
+    /// terrain, the nearest tiles' weights are used.
+    pub fn biome_weights(&self, p: glam::Vec2) -> glam::Vec3 {
+        let t = p / self.tile_span() - 0.5;
+        let t0 = t.floor();
+        let f = t - t0;
+
+        let last = self.size as i32 - 1;
+        let at = |dx: i32, dz: i32| {
+            let x = (t0.x as i32 + dx).clamp(0, last) as u32;
+            let z = (t0.y as i32 + dz).clamp(0, last) as u32;
+            self.tile((x, z))
+                .map_or_else(BiomeWeights::default, |tile| tile.biome)
+                .to_vec3()
+        };
+
+        let w0 = at(0, 0) + (at(1, 0) - at(0, 0)) * f.x;
+        let w1 = at(0, 1) + (at(1, 1) - at(0, 1)) * f.x;
+        w0 + (w1 - w0) * f.y
+    }
+
+    /// Splits the terrain into regions a few tiles wide, each given one
+    /// biome by the seed, and fades the regions into each other.
+    pub(crate) fn place_biomes(&mut self) {
+        let s = noise::NoiseSeed::new(self.seed);
+        for tile in &mut self.tiles {
+            let c = (glam::vec2(tile.id.0 as f32, tile.id.1 as f32) + 0.5) / REGION_TILES;
+            let g = c.floor();
+
+            let mut w = glam::Vec3::ZERO;
+            for j in -1..=1 {
+                for i in -1..=1 {
+                    let cell = g + glam::vec2(i as f32, j as f32);
+                    let center =
+                        cell + glam::vec2(noise::hash(cell, s), noise::hash(cell + 17.0, s));
+                    let index = (noise::hash(cell + 31.0, s) * Biome::ALL.len() as f32) as usize;
+                    let biome = Biome::ALL[index.min(Biome::ALL.len() - 1)];
+                    let d = center.distance(c);
+                    w += BiomeWeights::only(biome).to_vec3() * (-REGION_FALLOFF * d * d).exp();
+                }
+            }
+
+            tile.biome = BiomeWeights::from_vec3(w / w.element_sum());
+        }
+    }
+
+    /// Gives the tiles the layout every terrain had before biomes could be
+    /// placed: mountains in a circle around the middle of the first 32
+    /// tiles, surrounded by dunes.
+    pub(crate) fn place_legacy_biomes(&mut self) {
+        let span = self.tile_span();
+        let tile_size = self.tile_size;
+        for tile in &mut self.tiles {
+            let p = (glam::vec2(tile.id.0 as f32, tile.id.1 as f32) + 0.5) * span;
+            let f = legacy_mountains(p, tile_size);
+            tile.biome = BiomeWeights {
+                mountains: f.max(0.0),
+                dunes: (1.0 - f).max(0.0),
+                spires: 0.0,
+            };
+        }
+    }
+}
+
+// 3.14159 is the literal the shader used, not `PI`.
+#[allow(clippy::approx_constant)]
+fn legacy_mountains(p: glam::Vec2, tile_size: u32) -> f32 {
+    let r = 200.0;
+    let c = glam::Vec2::splat(tile_size as f32 * 16.0);
+
+    let d = (p - c).length() - r;
+
+    let f = d / r * 0.5 + 0.5;
+    (f * 3.14159).cos() * 0.5 + 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::terrain::heightfield::tests::fixture;
+
+    #[test]
+    fn weights_interpolate_between_tile_centres() {
+        let mut terrain = fixture(2, 9, Biome::Mountains, |_, _| 0.0);
+        terrain.tiles[1].biome = BiomeWeights::only(Biome::Spires);
+
+        // Tile centres are 8 apart, at 4 and 12
+        let at = |x: f32, z: f32| terrain.biome_weights(glam::vec2(x, z));
+        assert_eq!(at(4.0, 4.0), glam::Vec3::X);
+        assert_eq!(at(12.0, 4.0), glam::Vec3::Z);
+        assert_eq!(at(8.0, 4.0), glam::vec3(0.5, 0.0, 0.5));
+        // Past the last centre, and off the terrain, the edge is kept
+        assert_eq!(at(16.0, 0.0), glam::Vec3::Z);
+        assert_eq!(at(-50.0, 4.0), glam::Vec3::X);
+    }
+
+    #[test]
+    fn generates_every_biome() {
+        let terrain = Terrain::generate(16, 9, 100.0, 10.0, 25.0, 7);
+        let weights = terrain.tiles.iter().map(|t| t.biome).collect::<Vec<_>>();
+
+        // Every biome shows up somewhere, and weights are normalised
+        for biome in Biome::ALL {
+            let dominant = weights.iter().any(|w| w.to_vec3()[biome as usize] > 0.5);
+            assert!(dominant, "no {biome:?}");
+        }
+        for w in weights {
+            assert!((w.to_vec3().element_sum() - 1.0).abs() < 1e-5);
+            w.validate().unwrap();
+        }
+    }
+}
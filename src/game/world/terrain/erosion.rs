@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::world::terrain::{
+    Terrain,
+    heightfield::{HeightChanges, HeightField, Rng},
+};
+
+/// Least sediment a droplet can carry, so droplets on flat ground still
+/// erode a little.
+const MIN_SEDIMENT_CAPACITY: f32 = 0.01;
+
+/// Settings for [`erode`], which simulates raindrops running
+/// downhill over the terrain, wearing away the ground they flow quickly
+/// over and dropping it where they slow down.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErosionOptions {
+    /// How many droplets to simulate, one after another.
+    pub iterations: u32,
+    /// Most steps of one vertex each a droplet takes before it's dropped.
+    pub max_steps: u32,
+    /// Water each droplet starts with.
+    pub rain: f32,
+    /// How much a droplet keeps going the way it was, rather than turning
+    /// downhill, from 0 to 1.
+    pub inertia: f32,
+    /// Sediment a droplet can carry, per unit of water, speed and drop in
+    /// height.
+    pub sediment_capacity: f32,
+    /// Fraction of the sediment it has room for a droplet picks up each
+    /// step, from 0 to 1.
+    pub erosion: f32,
+    /// Fraction of the sediment over its capacity a droplet drops each
+    /// step, from 0 to 1.
+    pub deposition: f32,
+    /// Fraction of its water a droplet loses each step, from 0 to 1.
+    pub evaporation: f32,
+    /// How quickly droplets speed up going downhill.
+    pub gravity: f32,
+    /// Distance around a droplet it erodes, in world units.
+    pub radius: f32,
+}
+
+impl Default for ErosionOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 200_000,
+            max_steps: 64,
+            rain: 1.0,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            erosion: 0.3,
+            deposition: 0.3,
+            evaporation: 0.02,
+            gravity: 4.0,
+            radius: 3.0,
+        }
+    }
+}
+
+impl ErosionOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("inertia", self.inertia),
+            ("erosion", self.erosion),
+            ("deposition", self.deposition),
+            ("evaporation", self.evaporation),
+        ] {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&value),
+                "{name} must be from 0 to 1, not {value}"
+            );
+        }
+        for (name, value) in [
+            ("rain", self.rain),
+            ("sediment_capacity", self.sediment_capacity),
+            ("gravity", self.gravity),
+        ] {
+            anyhow::ensure!(
+                value.is_finite() && value >= 0.0,
+                "{name} can't be negative, not {value}"
+            );
+        }
+        anyhow::ensure!(
+            self.radius.is_finite() && self.radius >= 1.0,
+            "radius must be at least 1, not {}",
+            self.radius
+        );
+        Ok(())
+    }
+}
+
+/// Erodes the terrain's heights as they're baked, without changing the
+/// terrain. The same seed drops the same droplets.
+pub fn erode(terrain: &Terrain, options: &ErosionOptions, seed: u32) -> HeightChanges {
+    let original = HeightField::bake(terrain);
+    let mut field = original.clone();
+
+    let brush = erosion_brush(options.radius);
+    let mut rng = Rng::new(seed);
+    let max = (field.width - 1) as f32;
+    for _ in 0..options.iterations {
+        let start = glam::vec2(rng.next_f32(), rng.next_f32()) * max;
+        field.droplet(start, options, &brush);
+    }
+
+    field.changes(&original, terrain)
+}
+
+impl HeightField {
+    /// Follows one droplet from `p` until it evaporates, stops or runs off
+    /// the terrain.
+    fn droplet(&mut self, mut p: glam::Vec2, options: &ErosionOptions, brush: &[(i32, i32, f32)]) {
+        let max = (self.width - 1) as f32;
+        let mut direction = glam::Vec2::ZERO;
+        let mut speed = 1.0;
+        let mut water = options.rain;
+        let mut sediment = 0.0;
+
+        for _ in 0..options.max_steps {
+            let (height, gradient) = self.sample(p);
+            direction = direction * options.inertia - gradient * (1.0 - options.inertia);
+            let Some(step) = direction.try_normalize() else {
+                break;
+            };
+            direction = step;
+
+            let next = p + direction;
+            if next.cmplt(glam::Vec2::ZERO).any() || next.cmpge(glam::Vec2::splat(max)).any() {
+                break;
+            }
+
+            let dh = self.sample(next).0 - height;
+            let capacity =
+                (-dh * speed * water * options.sediment_capacity).max(MIN_SEDIMENT_CAPACITY);
+            if sediment > capacity || dh > 0.0 {
+                // Fill in what the droplet climbed out of, or drop what it
+                // can't carry
+                let amount = if dh > 0.0 {
+                    dh.min(sediment)
+                } else {
+                    (sediment - capacity) * options.deposition
+                };
+                sediment -= amount;
+                self.deposit(p, amount);
+            } else {
+                // Never dig deeper than where the droplet is going
+                let amount = ((capacity - sediment) * options.erosion).min(-dh);
+                sediment += amount;
+                self.erode(p, amount, brush);
+            }
+
+            speed = (speed * speed - dh * options.gravity).max(0.0).sqrt();
+            water *= 1.0 - options.evaporation;
+            p = next;
+        }
+    }
+
+    /// Spreads `amount` over the four vertices around `p`.
+    fn deposit(&mut self, p: glam::Vec2, amount: f32) {
+        let node = p.floor();
+        let f = p - node;
+        let (x, z) = (node.x as i32, node.y as i32);
+        for (dx, dz, weight) in [
+            (0, 0, (1.0 - f.x) * (1.0 - f.y)),
+            (1, 0, f.x * (1.0 - f.y)),
+            (0, 1, (1.0 - f.x) * f.y),
+            (1, 1, f.x * f.y),
+        ] {
+            let i = self.index(x + dx, z + dz);
+            self.heights[i] += amount * weight;
+        }
+    }
+
+    /// Takes `amount` from the vertices `brush` covers around `p`, leaving
+    /// out any off the terrain.
+    fn erode(&mut self, p: glam::Vec2, amount: f32, brush: &[(i32, i32, f32)]) {
+        let (x, z) = (p.x as i32, p.y as i32);
+        let width = self.width as i32;
+        let on_terrain = |&&(dx, dz, _): &&(i32, i32, f32)| {
+            (0..width).contains(&(x + dx)) && (0..width).contains(&(z + dz))
+        };
+
+        let total = brush.iter().filter(on_terrain).map(|b| b.2).sum::<f32>();
+        for &(dx, dz, weight) in brush.iter().filter(on_terrain) {
+            let i = self.index(x + dx, z + dz);
+            self.heights[i] -= amount * weight / total;
+        }
+    }
+}
+
+/// Offsets of the vertices within `radius` of a droplet, weighted by how
+/// close they are.
+fn erosion_brush(radius: f32) -> Vec<(i32, i32, f32)> {
+    let reach = radius.ceil() as i32;
+    let mut brush = Vec::new();
+    for dz in -reach..=reach {
+        for dx in -reach..=reach {
+            let weight = radius - ((dx * dx + dz * dz) as f32).sqrt();
+            if weight > 0.0 {
+                brush.push((dx, dz, weight));
+            }
+        }
+    }
+    brush
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::terrain::{HeightMode, biome::Biome, heightfield::tests::fixture};
+
+    /// Slopes down from a ridge at x = 0 to flat ground from x = 32 on.
+    fn slope(x: u32, _z: u32) -> f32 {
+        (32.0 - x as f32).max(0.0)
+    }
+
+    #[test]
+    fn erosion_moves_ground_downhill() {
+        let original = fixture(2, 33, Biome::Mountains, slope);
+        let mut terrain = original.clone();
+        let options = ErosionOptions {
+            iterations: 2000,
+            ..Default::default()
+        };
+        let changed = erode(&terrain, &options, 1).apply(&mut terrain);
+        assert!(!changed.is_empty());
+
+        // The slope is worn away and some of it ends up on the flat
+        let change = |x0: u32, x1: u32| {
+            let mut sum = 0.0;
+            for z in 0..=64 {
+                for x in x0..x1 {
+                    sum += terrain.height_at(x as f32, z as f32)
+                        - original.height_at(x as f32, z as f32);
+                }
+            }
+            sum
+        };
+        assert!(change(0, 28) < 0.0);
+        assert!(change(36, 65) > 0.0);
+        assert!(terrain.tiles.iter().all(|tile| {
+            let heights = tile.heights.as_ref().unwrap();
+            heights.mode == HeightMode::Replace && heights.samples.iter().all(|h| h.is_finite())
+        }));
+    }
+
+    #[test]
+    fn erosion_keeps_shared_edges_together() {
+        let mut terrain = fixture(2, 33, Biome::Mountains, slope);
+        let options = ErosionOptions {
+            iterations: 2000,
+            ..Default::default()
+        };
+        erode(&terrain, &options, 1).apply(&mut terrain);
+
+        let [left, right] = [(0, 0), (1, 0)].map(|id| terrain.tile_heights(id).unwrap());
+        for z in 0..33 {
+            assert_eq!(left.samples[32 + z * 33], right.samples[z * 33]);
+        }
+    }
+}
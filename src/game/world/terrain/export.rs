@@ -0,0 +1,150 @@
+use std::io::Cursor;
+
+use image::{ImageBuffer, ImageFormat, Luma, Rgb};
+use serde::Serialize;
+
+use crate::game::world::terrain::Terrain;
+
+/// Whether to export the terrain as one image or as one image per tile.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ExportLayout {
+    #[default]
+    Single,
+    PerTile,
+}
+
+/// Written next to the images, so other tools can turn the 16-bit
+/// heights back into world units.
+#[derive(Debug, Serialize)]
+struct HeightmapInfo {
+    min_height: f32,
+    max_height: f32,
+    tile_size: u32,
+    size: u32,
+}
+
+/// Bakes the terrain into 16-bit grayscale PNG heightmaps and RGB normal
+/// maps, one pixel per vertex. Returns the files to write as
+/// `(file name, contents)`.
+///
+/// Heights are scaled so the lowest point of the terrain is black and the
+/// highest is white. Normals use the OpenGL convention: red is +x, green
+/// is -z, which is up the image, and blue is +y.
+pub fn export_heightmaps(
+    terrain: &Terrain,
+    layout: ExportLayout,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let tile_span = terrain.tile_size - 1;
+    let samples = terrain.size * tile_span + 1;
+
+    let heights = (0..samples * samples)
+        .map(|i| terrain.height_at((i % samples) as f32, (i / samples) as f32))
+        .collect::<Vec<_>>();
+    let min_height = heights.iter().copied().fold(f32::MAX, f32::min);
+    let max_height = heights.iter().copied().fold(f32::MIN, f32::max);
+    let range = (max_height - min_height).max(f32::EPSILON);
+
+    let heightmap = ImageBuffer::from_fn(samples, samples, |x, z| {
+        let h = heights[(x + z * samples) as usize];
+        Luma([((h - min_height) / range * u16::MAX as f32).round() as u16])
+    });
+    let normal_map = ImageBuffer::from_fn(samples, samples, |x, z| {
+        let n = terrain.normal_at(x as f32, z as f32);
+        let n = glam::vec3(n.x, -n.z, n.y) * 0.5 + 0.5;
+        Rgb((n * 255.0).round().to_array().map(|c| c as u8))
+    });
+
+    let info = HeightmapInfo {
+        min_height,
+        max_height,
+        tile_size: terrain.tile_size,
+        size: terrain.size,
+    };
+    let mut files = vec![(
+        "heightmap.json".to_string(),
+        serde_json::to_string_pretty(&info)?.into_bytes(),
+    )];
+
+    match layout {
+        ExportLayout::Single => {
+            files.push(("heightmap.png".to_string(), encode_png(&heightmap)?));
+            files.push(("normal_map.png".to_string(), encode_png(&normal_map)?));
+        }
+        ExportLayout::PerTile => {
+            for tile in &terrain.tiles {
+                let (x, z) = (tile.id.0 * tile_span, tile.id.1 * tile_span);
+                let size = terrain.tile_size;
+                let (tx, tz) = tile.id;
+                files.push((
+                    format!("heightmap_{tx}_{tz}.png"),
+                    encode_png(
+                        &image::imageops::crop_imm(&heightmap, x, z, size, size).to_image(),
+                    )?,
+                ));
+                files.push((
+                    format!("normal_map_{tx}_{tz}.png"),
+                    encode_png(
+                        &image::imageops::crop_imm(&normal_map, x, z, size, size).to_image(),
+                    )?,
+                ));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn encode_png<P>(image: &ImageBuffer<P, Vec<P::Subpixel>>) -> anyhow::Result<Vec<u8>>
+where
+    P: image::PixelWithColorType,
+    [P::Subpixel]: image::EncodableLayout,
+{
+    let mut data = Cursor::new(Vec::new());
+    image.write_to(&mut data, ImageFormat::Png)?;
+    Ok(data.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_full_range_heightmap() {
+        let terrain = Terrain::generate(2, 8, 100.0, 10.0, 25.0, 0);
+        let files = export_heightmaps(&terrain, ExportLayout::Single).unwrap();
+        let names = files
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["heightmap.json", "heightmap.png", "normal_map.png"]);
+
+        let heightmap = image::load_from_memory(&files[1].1).unwrap().into_luma16();
+        assert_eq!(heightmap.dimensions(), (15, 15));
+        let (min, max) = heightmap.pixels().fold((u16::MAX, 0), |(min, max), p| {
+            (min.min(p.0[0]), max.max(p.0[0]))
+        });
+        assert_eq!((min, max), (0, u16::MAX));
+
+        let normal_map = image::load_from_memory(&files[2].1).unwrap().into_rgb8();
+        let n = terrain.normal_at(3.0, 5.0);
+        let pixel = normal_map.get_pixel(3, 5).0;
+        assert!((pixel[2] as f32 / 255.0 * 2.0 - 1.0 - n.y).abs() < 0.01);
+    }
+
+    #[test]
+    fn per_tile_images_share_edges() {
+        let terrain = Terrain::generate(2, 8, 100.0, 10.0, 25.0, 0);
+        let files = export_heightmaps(&terrain, ExportLayout::PerTile).unwrap();
+        assert_eq!(files.len(), 1 + 2 * 4);
+
+        let tile = |name: &str| {
+            let (_, data) = files.iter().find(|(n, _)| n == name).unwrap();
+            image::load_from_memory(data).unwrap().into_luma16()
+        };
+        let (a, b) = (tile("heightmap_0_0.png"), tile("heightmap_1_0.png"));
+        assert_eq!(a.dimensions(), (8, 8));
+        for z in 0..8 {
+            assert_eq!(a.get_pixel(7, z), b.get_pixel(0, z));
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::io::{Read, Write};
+
+use anyhow::Context;
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+
+use crate::game::world::terrain::{
+    HeightMode, Terrain, TerrainNoise, TerrainTile, TileHeights, biome::BiomeWeights,
+};
+
+/// First bytes of every binary terrain file.
+const MAGIC: &[u8; 4] = b"DIRT";
+/// Version written by [`encode`]. Bump it whenever the body layout
+/// changes, and keep a reader for every older version in [`decode_body`].
+const VERSION: u16 = 4;
+/// Set in the header when the body is deflate compressed.
+const FLAG_DEFLATE: u16 = 1;
+
+const PAYLOAD_NONE: u8 = 0;
+const PAYLOAD_HEIGHTS: u8 = 1;
+
+/// Writes the terrain in the binary `.dirt` format.
+///
+/// The file starts with an 8 byte header: the magic `DIRT`, then the
+/// format version and flags as little endian `u16`s. The deflate
+/// compressed body holds the terrain's seed, noise and settings followed
+/// by every tile, each with its biome weights and an optional payload of
+/// height samples.
+///
+/// The noise is stored as length-prefixed JSON, so new parameters can be
+/// added with a default without a new version.
+pub fn encode(terrain: &Terrain) -> anyhow::Result<Vec<u8>> {
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&FLAG_DEFLATE.to_le_bytes());
+    let mut encoder = DeflateEncoder::new(data, Compression::default());
+    encoder.write_all(&encode_body(terrain)?)?;
+    Ok(encoder.finish()?)
+}
+
+fn encode_body(terrain: &Terrain) -> anyhow::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    put_u32(&mut body, terrain.seed);
+    let noise = serde_json::to_vec(&terrain.noise)?;
+    put_u32(&mut body, noise.len() as u32);
+    body.extend_from_slice(&noise);
+    put_f32(&mut body, terrain.mountain_height);
+    put_f32(&mut body, terrain.dune_height);
+    put_f32(&mut body, terrain.spire_height);
+    put_u32(&mut body, terrain.size);
+    put_u32(&mut body, terrain.tile_size);
+    put_u32(&mut body, terrain.tiles.len() as u32);
+    for tile in &terrain.tiles {
+        put_u32(&mut body, tile.id.0);
+        put_u32(&mut body, tile.id.1);
+        for weight in tile.biome.to_vec3().to_array() {
+            put_f32(&mut body, weight);
+        }
+        match &tile.heights {
+            None => body.push(PAYLOAD_NONE),
+            Some(heights) => {
+                body.push(PAYLOAD_HEIGHTS);
+                body.push(match heights.mode {
+                    HeightMode::Offset => 0,
+                    HeightMode::Replace => 1,
+                });
+                put_u32(&mut body, heights.samples.len() as u32);
+                for &sample in &heights.samples {
+                    put_f32(&mut body, sample);
+                }
+            }
+        }
+    }
+    Ok(body)
+}
+
+/// Reads a terrain saved by [`encode`], by any older version of it, or as
+/// JSON from before the binary format existed. The terrain is validated
+/// before it is returned.
+pub fn decode(data: &[u8]) -> anyhow::Result<Terrain> {
+    let terrain = match data.strip_prefix(MAGIC) {
+        Some(header) => decode_binary(header)?,
+        None if data.trim_ascii_start().starts_with(b"{") => {
+            let mut terrain: Terrain =
+                serde_json::from_slice(data).context("Could not parse legacy JSON terrain")?;
+            terrain.place_legacy_biomes();
+            terrain
+        }
+        None => anyhow::bail!("Not a terrain file"),
+    };
+    terrain.validate()?;
+    Ok(terrain)
+}
+
+/// Reads everything after the magic of a binary terrain file.
+fn decode_binary(header: &[u8]) -> anyhow::Result<Terrain> {
+    let mut reader = Reader(header);
+    let version = reader.u16()?;
+    let flags = reader.u16()?;
+    anyhow::ensure!(
+        version <= VERSION,
+        "Terrain file version {version} is newer than the latest supported version {VERSION}"
+    );
+    anyhow::ensure!(
+        flags & !FLAG_DEFLATE == 0,
+        "Terrain file has unknown flags {flags:#06x}"
+    );
+
+    let body = if flags & FLAG_DEFLATE != 0 {
+        let mut body = Vec::new();
+        DeflateDecoder::new(reader.0)
+            .read_to_end(&mut body)
+            .context("Could not decompress terrain file")?;
+        body
+    } else {
+        reader.0.to_vec()
+    };
+
+    decode_body(version, &body).with_context(|| format!("Invalid version {version} terrain file"))
+}
+
+/// Reads the body of a file of the given version and migrates it to the
+/// current `Terrain`.
+fn decode_body(version: u16, body: &[u8]) -> anyhow::Result<Terrain> {
+    match version {
+        1 => decode_tiles(version, &mut Reader(body)),
+        // Version 2 added the seed in front of the version 1 layout
+        2 => {
+            let mut reader = Reader(body);
+            let seed = reader.u32()?;
+            Ok(Terrain {
+                seed,
+                ..decode_tiles(version, &mut reader)?
+            })
+        }
+        // Version 3 added the noise after the seed, and version 4 biome
+        // weights to every tile
+        3 | 4 => {
+            let mut reader = Reader(body);
+            let seed = reader.u32()?;
+            let len = reader.u32()? as usize;
+            let noise = serde_json::from_slice(reader.take(len)?)
+                .context("Could not parse terrain noise")?;
+            Ok(Terrain {
+                seed,
+                noise,
+                ..decode_tiles(version, &mut reader)?
+            })
+        }
+        _ => anyhow::bail!("Unknown terrain file version {version}"),
+    }
+}
+
+/// Reads the settings and tiles that every version has. Version 1 files
+/// are from before terrains had seeds or noise settings, so they get seed 0
+/// and the default noise. Files from before version 4 get the biomes every
+/// terrain had then.
+fn decode_tiles(version: u16, reader: &mut Reader) -> anyhow::Result<Terrain> {
+    let mountain_height = reader.f32()?;
+    let dune_height = reader.f32()?;
+    let spire_height = reader.f32()?;
+    let size = reader.u32()?;
+    let tile_size = reader.u32()?;
+
+    let count = reader.u32()?;
+    let mut tiles = Vec::with_capacity((count as usize).min(reader.0.len() / 9));
+    for _ in 0..count {
+        let id = (reader.u32()?, reader.u32()?);
+        let biome = if version >= 4 {
+            BiomeWeights::from_vec3(glam::vec3(reader.f32()?, reader.f32()?, reader.f32()?))
+        } else {
+            BiomeWeights::default()
+        };
+        let heights = match reader.u8()? {
+            PAYLOAD_NONE => None,
+            PAYLOAD_HEIGHTS => {
+                let mode = match reader.u8()? {
+                    0 => HeightMode::Offset,
+                    1 => HeightMode::Replace,
+                    mode => anyhow::bail!("Tile {id:?} has unknown height mode {mode}"),
+                };
+                let len = reader.u32()? as usize;
+                let samples = reader
+                    .take(len.checked_mul(4).context("Too many height samples")?)?
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect();
+                Some(TileHeights { mode, samples })
+            }
+            payload => anyhow::bail!("Tile {id:?} has unknown payload {payload}"),
+        };
+        tiles.push(TerrainTile { id, biome, heights });
+    }
+    anyhow::ensure!(
+        reader.0.is_empty(),
+        "{} unexpected bytes after the last tile",
+        reader.0.len()
+    );
+
+    let mut terrain = Terrain {
+        mountain_height,
+        dune_height,
+        spire_height,
+        seed: 0,
+        noise: TerrainNoise::default(),
+        size,
+        tile_size,
+        tiles,
+    };
+    if version < 4 {
+        terrain.place_legacy_biomes();
+    }
+    Ok(terrain)
+}
+
+fn put_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_f32(data: &mut Vec<u8>, value: f32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Reads little endian values from the front of a slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        anyhow::ensure!(self.0.len() >= len, "Terrain file ends early");
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into()?)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terrain() -> Terrain {
+        let mut terrain = Terrain::generate(4, 8, 100.0, 10.0, 25.0, 1234);
+        terrain.noise.mountains.octaves = 6;
+        terrain.noise.dunes.warp_amplitude = 0.5;
+        terrain.tiles[5].heights = Some(TileHeights {
+            mode: HeightMode::Replace,
+            samples: (0..64).map(|i| i as f32 * 0.5).collect(),
+        });
+        terrain.tiles[9].heights = Some(TileHeights {
+            mode: HeightMode::Offset,
+            samples: vec![-1.0; 64],
+        });
+        terrain
+    }
+
+    fn assert_same(a: &Terrain, b: &Terrain) {
+        assert_eq!(
+            serde_json::to_value(a).unwrap(),
+            serde_json::to_value(b).unwrap()
+        );
+    }
+
+    /// A file with the body stored as is.
+    fn uncompressed(terrain: &Terrain) -> Vec<u8> {
+        let mut data = b"DIRT\x04\x00\x00\x00".to_vec();
+        data.extend_from_slice(&encode_body(terrain).unwrap());
+        data
+    }
+
+    /// The body of a version 1 file, which is the settings and tiles of
+    /// later versions without the tiles' biome weights.
+    fn v1_body(terrain: &Terrain) -> Vec<u8> {
+        let body = encode_body(terrain).unwrap();
+        let noise_len = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+        let (settings, mut tiles) = body[8 + noise_len..].split_at(24);
+
+        let mut v1 = settings.to_vec();
+        while !tiles.is_empty() {
+            v1.extend_from_slice(&tiles[..8]);
+            let payload = match tiles[20] {
+                PAYLOAD_HEIGHTS => {
+                    let len = u32::from_le_bytes(tiles[22..26].try_into().unwrap());
+                    6 + len as usize * 4
+                }
+                _ => 1,
+            };
+            v1.extend_from_slice(&tiles[20..20 + payload]);
+            tiles = &tiles[20 + payload..];
+        }
+        v1
+    }
+
+    #[test]
+    fn round_trips_with_and_without_compression() {
+        let terrain = terrain();
+        let data = encode(&terrain).unwrap();
+        assert_eq!(&data[..8], b"DIRT\x04\x00\x01\x00");
+        assert_same(&decode(&data).unwrap(), &terrain);
+        assert_same(&decode(&uncompressed(&terrain)).unwrap(), &terrain);
+
+        // Tiles without heights take up little more than their biome
+        // weights
+        let flat = Terrain::generate(32, 32, 100.0, 10.0, 25.0, 0);
+        let data = encode(&flat).unwrap();
+        assert!(data.len() < 16 * 32 * 32, "{} bytes", data.len());
+        assert_same(&decode(&data).unwrap(), &flat);
+    }
+
+    #[test]
+    fn migrates_older_versions() {
+        let terrain = terrain();
+        let body = encode_body(&terrain).unwrap();
+        let seed = &body[..4];
+        let noise = &body[..8 + u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize];
+        let v1 = v1_body(&terrain);
+        let mut legacy = terrain.clone();
+        legacy.place_legacy_biomes();
+
+        let mut data = b"DIRT\x01\x00\x00\x00".to_vec();
+        data.extend_from_slice(&v1);
+        let expected = Terrain {
+            seed: 0,
+            noise: TerrainNoise::default(),
+            ..legacy.clone()
+        };
+        assert_same(&decode(&data).unwrap(), &expected);
+
+        // Version 2 added the seed
+        let mut data = b"DIRT\x02\x00\x00\x00".to_vec();
+        data.extend_from_slice(seed);
+        data.extend_from_slice(&v1);
+        let expected = Terrain {
+            noise: TerrainNoise::default(),
+            ..legacy.clone()
+        };
+        assert_same(&decode(&data).unwrap(), &expected);
+
+        // Version 3 added the noise
+        let mut data = b"DIRT\x03\x00\x00\x00".to_vec();
+        data.extend_from_slice(noise);
+        data.extend_from_slice(&v1);
+        assert_same(&decode(&data).unwrap(), &legacy);
+    }
+
+    #[test]
+    fn reads_legacy_json() {
+        let mut terrain = terrain();
+        let json = serde_json::to_string_pretty(&terrain).unwrap();
+        terrain.place_legacy_biomes();
+        assert_same(&decode(json.as_bytes()).unwrap(), &terrain);
+    }
+
+    #[test]
+    fn rejects_damaged_or_newer_files() {
+        let data = uncompressed(&terrain());
+        assert!(decode(&data[..data.len() - 1]).is_err());
+        assert!(decode(b"PNG whatever").is_err());
+
+        let mut newer = data.clone();
+        newer[4] = 5;
+        let error = decode(&newer).unwrap_err().to_string();
+        assert!(error.contains("newer"), "{error}");
+    }
+
+    #[test]
+    fn rejects_invalid_terrain() {
+        // Broken JSON points at where it went wrong
+        let error = decode(b"{\n  \"size\": 1,\n  \"tiles\": [}").unwrap_err();
+        let json = error
+            .chain()
+            .find_map(|e| e.downcast_ref::<serde_json::Error>())
+            .unwrap();
+        assert_eq!((json.line(), json.column()), (3, 13));
+
+        let mut terrain = terrain();
+        terrain.tiles[3].id = (4, 0);
+        let error = decode(&encode(&terrain).unwrap()).unwrap_err().to_string();
+        assert!(error.contains("outside"), "{error}");
+
+        terrain.tiles[3].id = terrain.tiles[2].id;
+        let error = decode(&encode(&terrain).unwrap()).unwrap_err().to_string();
+        assert!(error.contains("twice"), "{error}");
+
+        // Tiles need at least two vertices a side to have any levels of
+        // detail
+        let mut narrow = Terrain::generate(2, 1, 100.0, 10.0, 25.0, 0);
+        for tile_size in [0, 1] {
+            narrow.tile_size = tile_size;
+            let error = decode(&encode(&narrow).unwrap()).unwrap_err().to_string();
+            assert!(error.contains("tile_size"), "{error}");
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use crate::game::world::terrain::Terrain;
+
+/// Offsets of the eight vertices around a vertex, and how far away they
+/// are.
+pub(crate) const NEIGHBOURS: [(i32, i32, f32); 8] = [
+    (1, 0, 1.0),
+    (-1, 0, 1.0),
+    (0, 1, 1.0),
+    (0, -1, 1.0),
+    (1, 1, std::f32::consts::SQRT_2),
+    (-1, 1, std::f32::consts::SQRT_2),
+    (1, -1, std::f32::consts::SQRT_2),
+    (-1, -1, std::f32::consts::SQRT_2),
+];
+
+/// Heights of every vertex of a terrain as it's baked, row by row along x,
+/// for simulations that reshape the whole terrain at once.
+#[derive(Debug, Clone)]
+pub(crate) struct HeightField {
+    pub width: u32,
+    pub heights: Vec<f32>,
+}
+
+impl HeightField {
+    pub fn bake(terrain: &Terrain) -> Self {
+        let width = terrain.size * (terrain.tile_size - 1) + 1;
+        Self {
+            width,
+            heights: (0..width * width)
+                .map(|i| terrain.height_at((i % width) as f32, (i / width) as f32))
+                .collect(),
+        }
+    }
+
+    pub fn contains(&self, x: i32, z: i32) -> bool {
+        let width = self.width as i32;
+        (0..width).contains(&x) && (0..width).contains(&z)
+    }
+
+    pub fn index(&self, x: i32, z: i32) -> usize {
+        (x + z * self.width as i32) as usize
+    }
+
+    /// Bilinearly interpolated height and gradient at `p`, which has to
+    /// be at least one vertex from the far edges.
+    pub fn sample(&self, p: glam::Vec2) -> (f32, glam::Vec2) {
+        let node = p.floor();
+        let f = p - node;
+        let i = self.index(node.x as i32, node.y as i32);
+        let w = self.width as usize;
+        let (h00, h10) = (self.heights[i], self.heights[i + 1]);
+        let (h01, h11) = (self.heights[i + w], self.heights[i + w + 1]);
+
+        let gradient = glam::vec2(
+            (h10 - h00) * (1.0 - f.y) + (h11 - h01) * f.y,
+            (h01 - h00) * (1.0 - f.x) + (h11 - h10) * f.x,
+        );
+        let height = h00 * (1.0 - f.x) * (1.0 - f.y)
+            + h10 * f.x * (1.0 - f.y)
+            + h01 * (1.0 - f.x) * f.y
+            + h11 * f.x * f.y;
+        (height, gradient)
+    }
+
+    /// How far each vertex of the terrain's tiles moved from `original`
+    /// to these heights, leaving out tiles that didn't change.
+    pub fn changes(&self, original: &HeightField, terrain: &Terrain) -> HeightChanges {
+        let span = terrain.tile_size - 1;
+        let tiles = terrain
+            .tiles
+            .iter()
+            .filter_map(|tile| {
+                let origin = (tile.id.0 * span, tile.id.1 * span);
+                let deltas = (0..terrain.tile_size * terrain.tile_size)
+                    .map(|i| {
+                        let x = origin.0 + i % terrain.tile_size;
+                        let z = origin.1 + i / terrain.tile_size;
+                        let index = self.index(x as i32, z as i32);
+                        self.heights[index] - original.heights[index]
+                    })
+                    .collect::<Vec<_>>();
+                deltas
+                    .iter()
+                    .any(|&d| d != 0.0)
+                    .then_some((tile.id, deltas))
+            })
+            .collect();
+        HeightChanges { tiles }
+    }
+}
+
+/// How much a simulation moved each vertex of the tiles it changed, laid
+/// out like [`TileHeights`](super::TileHeights) samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightChanges {
+    pub tiles: Vec<((u32, u32), Vec<f32>)>,
+}
+
+impl HeightChanges {
+    /// Adds the changes to the terrain's tile heights. Returns the tiles
+    /// that changed.
+    pub fn apply(&self, terrain: &mut Terrain) -> Vec<(u32, u32)> {
+        let tile_size = terrain.tile_size;
+        self.tiles
+            .iter()
+            .filter_map(|(id, deltas)| {
+                let samples = terrain.tile_mut(*id)?.samples_mut(tile_size);
+                for (sample, delta) in samples.iter_mut().zip(deltas) {
+                    *sample += delta;
+                }
+                Some(*id)
+            })
+            .collect()
+    }
+}
+
+/// Xorshift random numbers, so simulations with the same seed run the
+/// same way on every platform.
+pub(crate) struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        Self(seed.wrapping_mul(747_796_405).wrapping_add(2_891_336_453) | 1)
+    }
+
+    /// A number from 0 up to, but not including, 1.
+    pub fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        (self.0 >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::game::world::terrain::{
+        HeightMode, TileHeights,
+        biome::{Biome, BiomeWeights},
+    };
+
+    /// A terrain of `size` by `size` tiles all of one biome, with every
+    /// vertex replaced by `height` at its position.
+    pub(crate) fn fixture(
+        size: u32,
+        tile_size: u32,
+        biome: Biome,
+        height: impl Fn(u32, u32) -> f32,
+    ) -> Terrain {
+        let mut terrain = Terrain::generate(size, tile_size, 100.0, 10.0, 25.0, 0);
+        let span = tile_size - 1;
+        for tile in &mut terrain.tiles {
+            tile.biome = BiomeWeights::only(biome);
+            let origin = (tile.id.0 * span, tile.id.1 * span);
+            tile.heights = Some(TileHeights {
+                mode: HeightMode::Replace,
+                samples: (0..tile_size * tile_size)
+                    .map(|i| height(origin.0 + i % tile_size, origin.1 + i / tile_size))
+                    .collect(),
+            });
+        }
+        terrain
+    }
+
+    #[test]
+    fn rng_follows_its_seed() {
+        let numbers = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..100).map(|_| rng.next_f32()).collect::<Vec<_>>()
+        };
+        assert_eq!(numbers(3), numbers(3));
+        assert_ne!(numbers(3), numbers(4));
+        assert!(numbers(0).iter().all(|n| (0.0..1.0).contains(n)));
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::Context;
+use image::{ImageBuffer, ImageFormat, Luma, imageops::FilterType};
+
+use crate::game::world::terrain::{
+    HeightMode, Terrain, TerrainNoise, TerrainTile, TileHeights, biome::BiomeWeights,
+};
+
+/// What to do with a heightmap that doesn't cover a whole number of tiles.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HeightmapFit {
+    /// Keep one pixel per vertex and pad the missing rows and columns
+    /// with the lowest height in the image.
+    #[default]
+    Pad,
+    /// Stretch the image over the nearest number of tiles.
+    Resample,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeightmapOptions {
+    /// Height of a white pixel.
+    pub vertical_scale: f32,
+    /// Subtracted from every height, so this becomes height zero.
+    pub sea_level: f32,
+    pub fit: HeightmapFit,
+}
+
+impl Default for HeightmapOptions {
+    fn default() -> Self {
+        Self {
+            vertical_scale: 100.0,
+            sea_level: 0.0,
+            fit: HeightmapFit::Pad,
+        }
+    }
+}
+
+/// Builds a terrain from a grayscale PNG or TIFF heightmap, with one pixel
+/// per vertex. Every tile replaces the procedural height with its slice
+/// of the image.
+pub fn import_heightmap(
+    data: &[u8],
+    tile_size: u32,
+    options: &HeightmapOptions,
+) -> anyhow::Result<Terrain> {
+    anyhow::ensure!(
+        tile_size >= 2,
+        "Tile size must be at least 2, not {tile_size}"
+    );
+
+    let format = image::guess_format(data).context("Unrecognized heightmap format")?;
+    anyhow::ensure!(
+        matches!(format, ImageFormat::Png | ImageFormat::Tiff),
+        "Heightmaps must be PNG or TIFF, not {format:?}"
+    );
+    let image =
+        image::load_from_memory_with_format(data, format).context("Could not decode heightmap")?;
+    if image.color().has_color() {
+        log::warn!(
+            "Heightmap is {:?}, using its luminance as height",
+            image.color()
+        );
+    }
+
+    let (width, height) = (image.width(), image.height());
+    anyhow::ensure!(width > 0 && height > 0, "Heightmap is empty");
+
+    let tile_span = tile_size - 1;
+    let longest = width.max(height);
+    let pixels = image.to_luma32f();
+    let (size, pixels) = match options.fit {
+        HeightmapFit::Pad => {
+            let size = (longest - 1).div_ceil(tile_span).max(1);
+            let samples = size * tile_span + 1;
+            if width != samples || height != samples {
+                log::warn!(
+                    "Heightmap is {width}x{height}, padding it to {samples}x{samples} to fill {size}x{size} tiles"
+                );
+            }
+            let lowest = pixels.pixels().map(|p| p.0[0]).fold(f32::MAX, f32::min);
+            let padded = ImageBuffer::from_fn(samples, samples, |x, z| {
+                *pixels.get_pixel_checked(x, z).unwrap_or(&Luma([lowest]))
+            });
+            (size, padded)
+        }
+        HeightmapFit::Resample => {
+            let size = ((longest - 1) as f32 / tile_span as f32).round().max(1.0) as u32;
+            let samples = size * tile_span + 1;
+            if width != samples || height != samples {
+                log::warn!(
+                    "Heightmap is {width}x{height}, resampling it to {samples}x{samples} to fill {size}x{size} tiles"
+                );
+            }
+            let resampled = if width != samples || height != samples {
+                image::imageops::resize(&pixels, samples, samples, FilterType::Triangle)
+            } else {
+                pixels
+            };
+            (size, resampled)
+        }
+    };
+
+    let mut tiles = Vec::with_capacity((size * size) as _);
+    for z in 0..size {
+        for x in 0..size {
+            let samples = (0..tile_size * tile_size)
+                .map(|i| {
+                    let px = x * tile_span + i % tile_size;
+                    let pz = z * tile_span + i / tile_size;
+                    pixels.get_pixel(px, pz).0[0] * options.vertical_scale - options.sea_level
+                })
+                .collect();
+            tiles.push(TerrainTile {
+                id: (x, z),
+                biome: BiomeWeights::default(),
+                heights: Some(TileHeights {
+                    mode: HeightMode::Replace,
+                    samples,
+                }),
+            });
+        }
+    }
+
+    log::info!("Imported a {width}x{height} heightmap as {size}x{size} tiles");
+
+    Ok(Terrain {
+        mountain_height: options.vertical_scale,
+        dune_height: 0.0,
+        spire_height: 0.0,
+        seed: 0,
+        noise: TerrainNoise::default(),
+        size,
+        tile_size,
+        tiles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn encode(image: image::DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, format).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn slices_16_bit_png_into_tiles() {
+        // 7x7 pixels is exactly 2x2 tiles of 4 vertices
+        let image = ImageBuffer::from_fn(7, 7, |x, z| Luma([(x * 1000 + z * 10) as u16]));
+        let data = encode(image.into(), ImageFormat::Png);
+        let options = HeightmapOptions {
+            vertical_scale: 65535.0,
+            sea_level: 5.0,
+            fit: HeightmapFit::Pad,
+        };
+
+        let terrain = import_heightmap(&data, 4, &options).unwrap();
+        assert_eq!(terrain.size, 2);
+        assert_eq!(terrain.tiles.len(), 4);
+        for (x, z) in [(0, 0), (3, 3), (4, 2), (6, 6), (2, 5)] {
+            let expected = (x * 1000 + z * 10) as f32 - 5.0;
+            let actual = terrain.height_at(x as f32, z as f32);
+            assert!((actual - expected).abs() < 0.1, "({x}, {z}): {actual}");
+        }
+    }
+
+    #[test]
+    fn pads_or_resamples_mismatched_tiff() {
+        let image = ImageBuffer::from_pixel(5, 3, Luma([255u8]));
+        let data = encode(image.into(), ImageFormat::Tiff);
+
+        let padded = import_heightmap(&data, 4, &HeightmapOptions::default()).unwrap();
+        assert_eq!(padded.size, 2);
+        assert!((padded.height_at(4.0, 2.0) - 100.0).abs() < 1e-3);
+        assert!((padded.height_at(5.0, 5.0) - 100.0).abs() < 1e-3);
+
+        let options = HeightmapOptions {
+            fit: HeightmapFit::Resample,
+            ..Default::default()
+        };
+        let resampled = import_heightmap(&data, 4, &options).unwrap();
+        assert_eq!(resampled.size, 1);
+        assert!((resampled.height_at(3.0, 3.0) - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn pads_with_lowest_height() {
+        let image = ImageBuffer::from_fn(3, 2, |x, z| {
+            Luma([if (x, z) == (1, 1) { 51u8 } else { 255 }])
+        });
+        let data = encode(image.into(), ImageFormat::Png);
+
+        let terrain = import_heightmap(&data, 4, &HeightmapOptions::default()).unwrap();
+        assert_eq!(terrain.size, 1);
+        assert!((terrain.height_at(1.0, 1.0) - 20.0).abs() < 1e-3);
+        for (x, z) in [(3, 0), (0, 2), (3, 3)] {
+            let height = terrain.height_at(x as f32, z as f32);
+            assert!((height - 20.0).abs() < 1e-3, "({x}, {z}): {height}");
+        }
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        let image = ImageBuffer::from_pixel(4, 4, Luma([0u8]));
+        let data = encode(image.into(), ImageFormat::Bmp);
+        assert!(import_heightmap(&data, 4, &HeightmapOptions::default()).is_err());
+    }
+}
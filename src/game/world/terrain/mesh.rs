@@ -0,0 +1,363 @@
+use std::{fmt::Write, path::Path};
+
+use glam::{Vec2, Vec3};
+
+use crate::game::world::terrain::Terrain;
+
+/// File formats a terrain mesh can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeshFormat {
+    Obj,
+    /// Binary glTF.
+    Glb,
+    /// Binary STL, closed off with side walls and a base.
+    Stl,
+}
+
+impl MeshFormat {
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("obj") => Ok(Self::Obj),
+            Some("glb") => Ok(Self::Glb),
+            Some("stl") => Ok(Self::Stl),
+            _ => anyhow::bail!(
+                "Can't tell the mesh format of {}, use .obj, .glb or .stl",
+                path.display()
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MeshOptions {
+    /// First and last tile to export, inclusive. `None` exports every tile.
+    pub tiles: Option<((u32, u32), (u32, u32))>,
+    /// Level of detail to export at. Each level halves the resolution, the
+    /// same way the renderer's levels of detail do.
+    pub decimation: u32,
+    /// How far below the lowest point of the terrain the base of an STL
+    /// sits.
+    pub base_depth: f32,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            tiles: None,
+            decimation: 0,
+            base_depth: 5.0,
+        }
+    }
+}
+
+/// A grid of terrain vertices covering a range of tiles.
+pub struct TerrainMesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    indices: Vec<u32>,
+    columns: u32,
+    rows: u32,
+}
+
+impl TerrainMesh {
+    /// Samples the terrain with the same grid and triangles the renderer
+    /// draws each tile with, see `lod_indices` in `render/terrain.rs`.
+    pub fn build(terrain: &Terrain, options: &MeshOptions) -> anyhow::Result<Self> {
+        anyhow::ensure!(terrain.size > 0, "Terrain has no tiles");
+        let last = terrain.size - 1;
+        let (min, max) = options.tiles.unwrap_or(((0, 0), (last, last)));
+        anyhow::ensure!(
+            min.0 <= max.0 && min.1 <= max.1 && max.0 <= last && max.1 <= last,
+            "Tiles {min:?} to {max:?} aren't within the terrain's {0}x{0} tiles",
+            terrain.size
+        );
+
+        let span = terrain.tile_size - 1;
+        let stride = 1 << options.decimation.min(31);
+        let tile_lines = (0..span).step_by(stride).collect::<Vec<_>>();
+        let lines = |first: u32, last: u32| {
+            let mut lines = (first..=last)
+                .flat_map(|tile| tile_lines.iter().map(move |&line| tile * span + line))
+                .collect::<Vec<_>>();
+            lines.push((last + 1) * span);
+            lines
+        };
+        let xs = lines(min.0, max.0);
+        let zs = lines(min.1, max.1);
+
+        let origin = glam::vec2(xs[0] as f32, zs[0] as f32);
+        let extent = glam::vec2(xs[xs.len() - 1] as f32, zs[zs.len() - 1] as f32) - origin;
+
+        let mut positions = Vec::with_capacity(xs.len() * zs.len());
+        let mut normals = Vec::with_capacity(xs.len() * zs.len());
+        let mut uvs = Vec::with_capacity(xs.len() * zs.len());
+        for &z in &zs {
+            for &x in &xs {
+                let (x, z) = (x as f32, z as f32);
+                positions.push(glam::vec3(x, terrain.height_at(x, z), z));
+                normals.push(terrain.normal_at(x, z));
+                uvs.push((glam::vec2(x, z) - origin) / extent);
+            }
+        }
+
+        let columns = xs.len() as u32;
+        let rows = zs.len() as u32;
+        let mut indices = Vec::with_capacity(((columns - 1) * (rows - 1) * 6) as _);
+        for z in 0..rows - 1 {
+            for x in 0..columns - 1 {
+                let i00 = x + z * columns;
+                let i10 = i00 + 1;
+                let i01 = i00 + columns;
+                let i11 = i01 + 1;
+                indices.extend_from_slice(&[i00, i11, i10, i00, i01, i11]);
+            }
+        }
+
+        Ok(Self {
+            positions,
+            normals,
+            uvs,
+            indices,
+            columns,
+            rows,
+        })
+    }
+
+    pub fn encode(&self, format: MeshFormat, options: &MeshOptions) -> Vec<u8> {
+        match format {
+            MeshFormat::Obj => self.to_obj().into_bytes(),
+            MeshFormat::Glb => self.to_glb(),
+            MeshFormat::Stl => self.to_stl(options.base_depth),
+        }
+    }
+
+    pub fn to_obj(&self) -> String {
+        let mut obj = String::from("# Exported from dirt\no terrain\n");
+        for p in &self.positions {
+            writeln!(obj, "v {} {} {}", p.x, p.y, p.z).unwrap();
+        }
+        for uv in &self.uvs {
+            // OBJ texture coordinates start at the bottom of the image
+            writeln!(obj, "vt {} {}", uv.x, 1.0 - uv.y).unwrap();
+        }
+        for n in &self.normals {
+            writeln!(obj, "vn {} {} {}", n.x, n.y, n.z).unwrap();
+        }
+        for triangle in self.indices.chunks(3) {
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(obj, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}").unwrap();
+        }
+        obj
+    }
+
+    pub fn to_glb(&self) -> Vec<u8> {
+        const ARRAY_BUFFER: u32 = 34962;
+        const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+        const FLOAT: u32 = 5126;
+        const UNSIGNED_INT: u32 = 5125;
+
+        let mut bin = Vec::new();
+        let mut views = Vec::new();
+        let mut push_view = |data: &[u8], target: u32| {
+            views.push(serde_json::json!({
+                "buffer": 0,
+                "byteOffset": bin.len(),
+                "byteLength": data.len(),
+                "target": target,
+            }));
+            bin.extend_from_slice(data);
+        };
+        push_view(bytemuck::cast_slice(&self.positions), ARRAY_BUFFER);
+        push_view(bytemuck::cast_slice(&self.normals), ARRAY_BUFFER);
+        push_view(bytemuck::cast_slice(&self.uvs), ARRAY_BUFFER);
+        push_view(bytemuck::cast_slice(&self.indices), ELEMENT_ARRAY_BUFFER);
+
+        let min = self.positions.iter().copied().fold(Vec3::MAX, Vec3::min);
+        let max = self.positions.iter().copied().fold(Vec3::MIN, Vec3::max);
+        let count = self.positions.len();
+        let json = serde_json::json!({
+            "asset": { "version": "2.0", "generator": "dirt" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0, "name": "terrain" }],
+            "meshes": [{
+                "primitives": [{
+                    "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                    "indices": 3,
+                }],
+            }],
+            "buffers": [{ "byteLength": bin.len() }],
+            "bufferViews": views,
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": FLOAT,
+                    "count": count,
+                    "type": "VEC3",
+                    "min": min.to_array(),
+                    "max": max.to_array(),
+                },
+                { "bufferView": 1, "componentType": FLOAT, "count": count, "type": "VEC3" },
+                { "bufferView": 2, "componentType": FLOAT, "count": count, "type": "VEC2" },
+                {
+                    "bufferView": 3,
+                    "componentType": UNSIGNED_INT,
+                    "count": self.indices.len(),
+                    "type": "SCALAR",
+                },
+            ],
+        });
+
+        // Chunks have to be 4 byte aligned, JSON is padded with spaces
+        let mut json = json.to_string().into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    pub fn to_stl(&self, base_depth: f32) -> Vec<u8> {
+        let triangles = self.solid(base_depth);
+
+        let mut stl = Vec::with_capacity(84 + triangles.len() * 50);
+        let mut header = [0; 80];
+        header[..18].copy_from_slice(b"Exported from dirt");
+        stl.extend_from_slice(&header);
+        stl.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for [a, b, c] in triangles {
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            for v in [normal, a, b, c] {
+                stl.extend_from_slice(bytemuck::bytes_of(&v));
+            }
+            stl.extend_from_slice(&0u16.to_le_bytes());
+        }
+        stl
+    }
+
+    /// Closes the surface off with walls down to a flat base, so it can be
+    /// printed. Every triangle faces out.
+    fn solid(&self, base_depth: f32) -> Vec<[Vec3; 3]> {
+        let base = self.positions.iter().map(|p| p.y).fold(f32::MAX, f32::min) - base_depth;
+        let bottom = |i: u32| {
+            let p = self.positions[i as usize];
+            glam::vec3(p.x, base, p.z)
+        };
+
+        let mut triangles = self
+            .indices
+            .chunks(3)
+            .map(|t| [t[0], t[1], t[2]].map(|i| self.positions[i as usize]))
+            .collect::<Vec<_>>();
+
+        // Edge vertices, going around the grid
+        let (columns, rows) = (self.columns, self.rows);
+        let mut edge = Vec::new();
+        edge.extend(0..columns - 1);
+        edge.extend((0..rows - 1).map(|z| columns - 1 + z * columns));
+        edge.extend((1..columns).rev().map(|x| x + (rows - 1) * columns));
+        edge.extend((1..rows).rev().map(|z| z * columns));
+
+        let center = edge.iter().map(|&i| bottom(i)).sum::<Vec3>() / edge.len() as f32;
+        let mut push_facing = |[a, b, c]: [Vec3; 3], outward: Vec3| {
+            if (b - a).cross(c - a).dot(outward) < 0.0 {
+                triangles.push([a, c, b]);
+            } else {
+                triangles.push([a, b, c]);
+            }
+        };
+
+        for (k, &i) in edge.iter().enumerate() {
+            let j = edge[(k + 1) % edge.len()];
+            let (top_i, top_j) = (self.positions[i as usize], self.positions[j as usize]);
+            let (bottom_i, bottom_j) = (bottom(i), bottom(j));
+            let outward = (bottom_i + bottom_j) * 0.5 - center;
+
+            push_facing([top_i, bottom_i, bottom_j], outward);
+            push_facing([top_i, bottom_j, top_j], outward);
+            push_facing([center, bottom_i, bottom_j], Vec3::NEG_Y);
+        }
+
+        triangles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn mesh(decimation: u32) -> TerrainMesh {
+        let terrain = Terrain::generate(3, 9, 100.0, 10.0, 25.0, 0);
+        let options = MeshOptions {
+            tiles: Some(((1, 0), (2, 1))),
+            decimation,
+            ..Default::default()
+        };
+        TerrainMesh::build(&terrain, &options).unwrap()
+    }
+
+    #[test]
+    fn grid_matches_tiles() {
+        let full = mesh(0);
+        assert_eq!((full.columns, full.rows), (17, 17));
+        assert_eq!(full.positions[0].x, 8.0);
+        assert_eq!(full.positions.last().unwrap().z, 16.0);
+
+        let decimated = mesh(2);
+        assert_eq!((decimated.columns, decimated.rows), (5, 5));
+        assert_eq!(decimated.indices.len(), 4 * 4 * 6);
+    }
+
+    #[test]
+    fn stl_is_watertight() {
+        let triangles = mesh(1).solid(5.0);
+
+        // Every edge of a closed, consistently wound mesh is used once in
+        // each direction
+        let key = |v: Vec3| v.to_array().map(f32::to_bits);
+        let mut edges = HashMap::new();
+        for t in &triangles {
+            for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                *edges.entry((key(a), key(b))).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+
+        let stl = mesh(1).to_stl(5.0);
+        assert_eq!(stl.len(), 84 + triangles.len() * 50);
+    }
+
+    #[test]
+    fn glb_has_valid_header() {
+        let glb = mesh(1).to_glb();
+        assert_eq!(&glb[..4], b"glTF");
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+
+        let json_len = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        let json: serde_json::Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+        assert_eq!(json["accessors"][0]["count"], 81);
+    }
+}
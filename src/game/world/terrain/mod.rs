@@ -0,0 +1,851 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::game::world::terrain::biome::BiomeWeights;
+
+pub mod biome;
+pub mod erosion;
+pub mod export;
+pub mod file;
+pub mod heightfield;
+pub mod import;
+pub mod mesh;
+pub mod noise;
+pub mod sand;
+pub mod sculpt;
+pub mod thermal;
+
+/// Shortest step [`Terrain::raycast`] takes. Features smaller than this can
+/// be missed by rays that only graze them.
+const RAYCAST_MIN_STEP: f32 = 0.25;
+/// Longest step [`Terrain::raycast`] takes.
+const RAYCAST_MAX_STEP: f32 = 2.0;
+/// Most octaves `FbmNoise` may have. Each one is another simplex noise
+/// sample per vertex.
+pub const MAX_FBM_OCTAVES: u32 = 16;
+/// Most terraces `SpireNoise` may have.
+pub const MAX_SPIRE_TERRACES: u32 = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Terrain {
+    pub mountain_height: f32,
+    pub dune_height: f32,
+    pub spire_height: f32,
+    /// Picks which of the possible procedural terrains this is. Files from
+    /// before seeds existed load with seed 0.
+    #[serde(default)]
+    pub seed: u32,
+    #[serde(default)]
+    pub noise: TerrainNoise,
+    pub size: u32,
+    pub tile_size: u32,
+    pub tiles: Vec<TerrainTile>,
+}
+
+impl Terrain {
+    pub(crate) fn generate(
+        terrain_size: u32,
+        tile_size: u32,
+        mountain_height: f32,
+        dune_height: f32,
+        spire_height: f32,
+        seed: u32,
+    ) -> Terrain {
+        let mut tiles = Vec::with_capacity((terrain_size * terrain_size) as _);
+
+        for z in 0..terrain_size {
+            for x in 0..terrain_size {
+                tiles.push(TerrainTile {
+                    id: (x, z),
+                    biome: BiomeWeights::default(),
+                    heights: None,
+                });
+            }
+        }
+
+        let mut terrain = Terrain {
+            mountain_height,
+            dune_height,
+            spire_height,
+            seed,
+            noise: TerrainNoise::default(),
+            size: terrain_size,
+            tile_size,
+            tiles,
+        };
+        terrain.place_biomes();
+        terrain
+    }
+
+    /// Checks the terrain makes sense, for terrains loaded from files.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.tile_size >= 2,
+            "tile_size must be at least 2, not {}",
+            self.tile_size
+        );
+        anyhow::ensure!(self.size >= 1, "size must be at least 1");
+        anyhow::ensure!(
+            self.noise.mountains.octaves <= MAX_FBM_OCTAVES,
+            "noise.mountains.octaves can be at most {MAX_FBM_OCTAVES}, not {}",
+            self.noise.mountains.octaves
+        );
+        anyhow::ensure!(
+            (1..=MAX_SPIRE_TERRACES).contains(&self.noise.spires.terraces),
+            "noise.spires.terraces must be from 1 to {MAX_SPIRE_TERRACES}, not {}",
+            self.noise.spires.terraces
+        );
+        for (name, height) in [
+            ("mountain_height", self.mountain_height),
+            ("dune_height", self.dune_height),
+            ("spire_height", self.spire_height),
+        ] {
+            anyhow::ensure!(height.is_finite(), "{name} must be a number, not {height}");
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        for tile in &self.tiles {
+            anyhow::ensure!(
+                tile.id.0 < self.size && tile.id.1 < self.size,
+                "Tile {:?} is outside a terrain of {} by {} tiles",
+                tile.id,
+                self.size,
+                self.size
+            );
+            anyhow::ensure!(seen.insert(tile.id), "Tile {:?} appears twice", tile.id);
+            tile.biome
+                .validate()
+                .with_context(|| format!("Tile {:?} has invalid biome weights", tile.id))?;
+        }
+        Ok(())
+    }
+
+    /// Width of a tile in world units. Neighbouring tiles share their
+    /// edge vertices, so this is one less than `tile_size`.
+    pub fn tile_span(&self) -> f32 {
+        (self.tile_size - 1) as f32
+    }
+
+    pub fn tile(&self, id: (u32, u32)) -> Option<&TerrainTile> {
+        // Tiles are generated row by row, so this is usually a direct hit
+        let index = (id.0 + id.1 * self.size) as usize;
+        match self.tiles.get(index) {
+            Some(tile) if tile.id == id => Some(tile),
+            _ => self.tiles.iter().find(|tile| tile.id == id),
+        }
+    }
+
+    pub fn tile_mut(&mut self, id: (u32, u32)) -> Option<&mut TerrainTile> {
+        let index = (id.0 + id.1 * self.size) as usize;
+        match self.tiles.get(index) {
+            Some(tile) if tile.id == id => self.tiles.get_mut(index),
+            _ => self.tiles.iter_mut().find(|tile| tile.id == id),
+        }
+    }
+
+    /// Height samples of a tile, if it has any and there is one for each
+    /// of its vertices.
+    pub fn tile_heights(&self, id: (u32, u32)) -> Option<&TileHeights> {
+        self.tile(id)?
+            .heights
+            .as_ref()
+            .filter(|heights| heights.samples.len() == (self.tile_size * self.tile_size) as usize)
+    }
+
+    /// Height of the terrain surface at world position `(x, z)`.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        self.point(glam::vec2(x, z)).y
+    }
+
+    /// Unit surface normal at world position `(x, z)`, using the same finite
+    /// differences as `tile_vertex` in `shaders/terrain_bake.wgsl`.
+    pub fn normal_at(&self, x: f32, z: f32) -> glam::Vec3 {
+        let p = glam::vec2(x, z);
+        let v = self.point(p);
+
+        let tpx = self.point(p + glam::vec2(0.01, 0.0)) - v;
+        let tnx = self.point(p + glam::vec2(-0.01, 0.0)) - v;
+
+        let tpz = self.point(p + glam::vec2(0.0, 0.01)) - v;
+        let tnz = self.point(p + glam::vec2(0.0, -0.01)) - v;
+
+        let pn = tpz.cross(tpx).normalize();
+        let nn = tnz.cross(tnx).normalize();
+
+        ((pn + nn) * 0.5).normalize()
+    }
+
+    /// Angle between the surface and the horizontal at `(x, z)`, in radians.
+    pub fn slope_at(&self, x: f32, z: f32) -> f32 {
+        self.normal_at(x, z).y.clamp(-1.0, 1.0).acos()
+    }
+
+    /// Tile covering world position `(x, z)`, if the terrain has one there.
+    /// The far edges of the terrain belong to the last row and column.
+    pub fn tile_at(&self, x: f32, z: f32) -> Option<(u32, u32)> {
+        let extent = self.size as f32 * self.tile_span();
+        if !(0.0..=extent).contains(&x) || !(0.0..=extent).contains(&z) {
+            return None;
+        }
+
+        let last = self.size.max(1) - 1;
+        let tile = (glam::vec2(x, z) / self.tile_span()).floor().as_uvec2();
+        let id = (tile.x.min(last), tile.y.min(last));
+        self.tile(id).map(|tile| tile.id)
+    }
+
+    /// Where the ray from `origin` along `direction` first hits the
+    /// terrain, looking no further than `max_distance`. Hits off the edge
+    /// of the terrain are ignored.
+    pub fn pick(
+        &self,
+        origin: glam::Vec3,
+        direction: glam::Vec3,
+        max_distance: f32,
+    ) -> Option<TerrainHit> {
+        let position = self.raycast(origin, direction, max_distance)?;
+        let tile = self.tile_at(position.x, position.z)?;
+        Some(TerrainHit {
+            position,
+            tile,
+            normal: self.normal_at(position.x, position.z),
+        })
+    }
+
+    /// First point where the ray from `origin` along `direction` hits the
+    /// ground, looking no further than `max_distance`.
+    pub fn raycast(
+        &self,
+        origin: glam::Vec3,
+        direction: glam::Vec3,
+        max_distance: f32,
+    ) -> Option<glam::Vec3> {
+        let direction = direction.normalize();
+        let above = |t: f32| {
+            let p = origin + direction * t;
+            p.y - self.height_at(p.x, p.z)
+        };
+
+        // March in steps that shrink as the ray nears the ground, then
+        // narrow down the crossing by bisection
+        let mut t = 0.0;
+        let mut gap = above(t);
+        if gap < 0.0 {
+            return None;
+        }
+        while t < max_distance {
+            let step = (gap * 0.5).clamp(RAYCAST_MIN_STEP, RAYCAST_MAX_STEP);
+            let next_gap = above(t + step);
+            if next_gap < 0.0 {
+                let (mut near, mut far) = (t, t + step);
+                for _ in 0..16 {
+                    let mid = (near + far) * 0.5;
+                    if above(mid) < 0.0 {
+                        far = mid;
+                    } else {
+                        near = mid;
+                    }
+                }
+                return Some(origin + direction * far);
+            }
+            t += step;
+            gap = next_gap;
+        }
+
+        None
+    }
+
+    fn point(&self, p: glam::Vec2) -> glam::Vec3 {
+        let blend = self.biome_blend(p);
+
+        let s = noise::NoiseSeed::new(self.seed);
+
+        let y0 = mountains(p, &self.noise.mountains, self.mountain_height, s);
+        let y1 = dunes(p, &self.noise.dunes, self.dune_height, s);
+        let y2 = spires(p, &self.noise.spires, self.spire_height, s);
+        // Spires stand on dunes, so the desert floor between them is the same
+        let yf = y0 * blend.x + y1 * (blend.y + blend.z) + y2 * blend.z;
+
+        let y = match self.sample_tile_heights(p) {
+            Some((HeightMode::Offset, h)) => yf + h,
+            Some((HeightMode::Replace, h)) => h,
+            None => yf,
+        };
+
+        glam::vec3(p.x, y, p.y)
+    }
+
+    /// Samples the heights of the tile `p` falls in, the same way
+    /// `tile_height` in `shaders/terrain_bake.wgsl` does. Off the terrain,
+    /// the nearest tile's edge is used.
+    fn sample_tile_heights(&self, p: glam::Vec2) -> Option<(HeightMode, f32)> {
+        let last = self.size.max(1) as f32 - 1.0;
+        let tile = (p / self.tile_span())
+            .floor()
+            .clamp(glam::Vec2::ZERO, glam::Vec2::splat(last));
+
+        let heights = self.tile_heights((tile.x as u32, tile.y as u32))?;
+        let local = p - tile * self.tile_span();
+        Some((heights.mode, heights.sample(local, self.tile_size)))
+    }
+
+    /// Weights of mountains, dunes and spires at `p`, adding up to one.
+    /// Spires only stand on part of the spire biome, the rest is dunes.
+    fn biome_blend(&self, p: glam::Vec2) -> glam::Vec4 {
+        let w = self.biome_weights(p);
+        let spires = w.z * spire_mask(p, &self.noise.spires, noise::NoiseSeed::new(self.seed));
+
+        let blend = glam::vec4(w.x, w.y + w.z - spires, spires, 0.0);
+
+        let sum = blend.x + blend.y + blend.z + blend.w;
+
+        blend * (1.0 / sum)
+    }
+}
+
+fn mountains(p: glam::Vec2, params: &FbmNoise, max_height: f32, s: noise::NoiseSeed) -> f32 {
+    noise::fbm(p, params, s) * max_height
+}
+
+fn dunes(p: glam::Vec2, params: &DuneNoise, max_height: f32, s: noise::NoiseSeed) -> f32 {
+    let warp = noise::snoise2(p * params.warp_frequency, s) * params.warp_amplitude;
+    noise::smooth_voronoi(p * params.frequency + warp, s) * max_height
+}
+
+fn spires(p: glam::Vec2, params: &SpireNoise, max_height: f32, s: noise::NoiseSeed) -> f32 {
+    noise::spire_columns(p, params, s) * max_height
+}
+
+fn spire_mask(p: glam::Vec2, params: &SpireNoise, s: noise::NoiseSeed) -> f32 {
+    let n = noise::snoise2(p * params.mask_frequency, s) * 0.5 + 0.5;
+    noise::smoothstep(0.95 - params.coverage, 1.05 - params.coverage, n)
+}
+
+/// Shape of the noise each biome is made from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TerrainNoise {
+    #[serde(default)]
+    pub mountains: FbmNoise,
+    #[serde(default)]
+    pub dunes: DuneNoise,
+    #[serde(default)]
+    pub spires: SpireNoise,
+}
+
+/// A noise parameter that can be changed while the game runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseParam {
+    MountainOctaves,
+    MountainFrequency,
+    MountainLacunarity,
+    MountainGain,
+    MountainRotation,
+    DuneFrequency,
+    DuneWarpFrequency,
+    DuneWarpAmplitude,
+    SpireFrequency,
+    SpireRadius,
+    SpireTerraces,
+    SpireRoughness,
+    SpireCoverage,
+}
+
+impl NoiseParam {
+    pub const ALL: [Self; 13] = [
+        Self::MountainOctaves,
+        Self::MountainFrequency,
+        Self::MountainLacunarity,
+        Self::MountainGain,
+        Self::MountainRotation,
+        Self::DuneFrequency,
+        Self::DuneWarpFrequency,
+        Self::DuneWarpAmplitude,
+        Self::SpireFrequency,
+        Self::SpireRadius,
+        Self::SpireTerraces,
+        Self::SpireRoughness,
+        Self::SpireCoverage,
+    ];
+
+    /// The parameter `offset` places after this one, wrapping around.
+    pub fn cycle(self, offset: i32) -> Self {
+        let index = Self::ALL.iter().position(|&p| p == self).unwrap() as i32;
+        Self::ALL[(index + offset).rem_euclid(Self::ALL.len() as i32) as usize]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::MountainOctaves => "mountains.octaves",
+            Self::MountainFrequency => "mountains.frequency",
+            Self::MountainLacunarity => "mountains.lacunarity",
+            Self::MountainGain => "mountains.gain",
+            Self::MountainRotation => "mountains.rotation",
+            Self::DuneFrequency => "dunes.frequency",
+            Self::DuneWarpFrequency => "dunes.warp_frequency",
+            Self::DuneWarpAmplitude => "dunes.warp_amplitude",
+            Self::SpireFrequency => "spires.frequency",
+            Self::SpireRadius => "spires.radius",
+            Self::SpireTerraces => "spires.terraces",
+            Self::SpireRoughness => "spires.roughness",
+            Self::SpireCoverage => "spires.coverage",
+        }
+    }
+
+    pub fn value(self, noise: &TerrainNoise) -> f32 {
+        match self {
+            Self::MountainOctaves => noise.mountains.octaves as f32,
+            Self::MountainFrequency => noise.mountains.frequency,
+            Self::MountainLacunarity => noise.mountains.lacunarity,
+            Self::MountainGain => noise.mountains.gain,
+            Self::MountainRotation => noise.mountains.rotation,
+            Self::DuneFrequency => noise.dunes.frequency,
+            Self::DuneWarpFrequency => noise.dunes.warp_frequency,
+            Self::DuneWarpAmplitude => noise.dunes.warp_amplitude,
+            Self::SpireFrequency => noise.spires.frequency,
+            Self::SpireRadius => noise.spires.radius,
+            Self::SpireTerraces => noise.spires.terraces as f32,
+            Self::SpireRoughness => noise.spires.roughness,
+            Self::SpireCoverage => noise.spires.coverage,
+        }
+    }
+
+    /// Moves the parameter up or down by `steps`. Octaves and terraces
+    /// change by one per step, angles and coverage by 0.05 and everything
+    /// else by 10%.
+    pub fn adjust(self, noise: &mut TerrainNoise, steps: i32) {
+        let scale = 1.1f32.powi(steps);
+        match self {
+            Self::MountainOctaves => {
+                let octaves = noise.mountains.octaves as i32 + steps;
+                noise.mountains.octaves = octaves.clamp(1, MAX_FBM_OCTAVES as i32) as u32;
+            }
+            Self::MountainFrequency => noise.mountains.frequency *= scale,
+            Self::MountainLacunarity => noise.mountains.lacunarity *= scale,
+            Self::MountainGain => noise.mountains.gain *= scale,
+            Self::MountainRotation => noise.mountains.rotation += 0.05 * steps as f32,
+            Self::DuneFrequency => noise.dunes.frequency *= scale,
+            Self::DuneWarpFrequency => noise.dunes.warp_frequency *= scale,
+            Self::DuneWarpAmplitude => noise.dunes.warp_amplitude *= scale,
+            Self::SpireFrequency => noise.spires.frequency *= scale,
+            Self::SpireRadius => noise.spires.radius *= scale,
+            Self::SpireTerraces => {
+                let terraces = noise.spires.terraces as i32 + steps;
+                noise.spires.terraces = terraces.clamp(1, MAX_SPIRE_TERRACES as i32) as u32;
+            }
+            Self::SpireRoughness => noise.spires.roughness *= scale,
+            Self::SpireCoverage => {
+                noise.spires.coverage =
+                    (noise.spires.coverage + 0.05 * steps as f32).clamp(0.0, 1.0);
+            }
+        }
+    }
+}
+
+/// Layers of simplex noise, each finer and fainter than the last.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FbmNoise {
+    pub octaves: u32,
+    /// Of the first octave, in cycles per world unit.
+    pub frequency: f32,
+    /// How much finer each octave is than the one before.
+    pub lacunarity: f32,
+    /// How much fainter each octave is than the one before.
+    pub gain: f32,
+    /// Angle each octave is turned by relative to the one before, in
+    /// radians. Keeps the octaves' grids from lining up.
+    pub rotation: f32,
+}
+
+impl FbmNoise {
+    /// Lowest and highest values `noise::fbm` can return, assuming simplex
+    /// noise stays within -1 to 1.
+    pub fn range(&self) -> (f32, f32) {
+        let amplitude = (0..self.octaves)
+            .map(|i| 0.5 * self.gain.abs().powi(i as i32))
+            .sum::<f32>();
+        (0.5 - amplitude * 0.5, 0.5 + amplitude * 0.5)
+    }
+}
+
+impl Default for FbmNoise {
+    fn default() -> Self {
+        Self {
+            octaves: 4,
+            frequency: 0.01,
+            lacunarity: 2.0,
+            gain: 0.5,
+            rotation: 0.5,
+        }
+    }
+}
+
+/// Voronoi cells whose edges are warped by simplex noise.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DuneNoise {
+    /// Of the cells, in cycles per world unit.
+    pub frequency: f32,
+    pub warp_frequency: f32,
+    /// How far the cells are pushed around, in cells.
+    pub warp_amplitude: f32,
+}
+
+impl Default for DuneNoise {
+    fn default() -> Self {
+        Self {
+            frequency: 0.05,
+            warp_frequency: 0.01,
+            warp_amplitude: 0.01,
+        }
+    }
+}
+
+/// Columns of rock standing in the desert, on a jittered grid of cells.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpireNoise {
+    /// Of the cells, in cycles per world unit. There is at most one spire
+    /// per cell.
+    pub frequency: f32,
+    /// Of the widest spires, in cells.
+    pub radius: f32,
+    /// Number of ledges the sides of the tallest spires are cut into.
+    pub terraces: u32,
+    /// How far the edges of the spires are pushed in and out, in cells.
+    pub roughness: f32,
+    /// Fraction of the spire biome with spires standing in it, from 0 to
+    /// 1. The rest is dunes.
+    pub coverage: f32,
+    /// Of the patches of desert that have spires, in cycles per world unit.
+    pub mask_frequency: f32,
+}
+
+impl Default for SpireNoise {
+    fn default() -> Self {
+        Self {
+            frequency: 0.02,
+            radius: 0.3,
+            terraces: 4,
+            roughness: 0.05,
+            coverage: 0.4,
+            mask_frequency: 0.004,
+        }
+    }
+}
+
+/// A point on the terrain found by [`Terrain::pick`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainHit {
+    pub position: glam::Vec3,
+    pub tile: (u32, u32),
+    pub normal: glam::Vec3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerrainTile {
+    pub id: (u32, u32),
+    #[serde(default)]
+    pub biome: BiomeWeights,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heights: Option<TileHeights>,
+}
+
+impl TerrainTile {
+    /// The tile's height samples for editing. A tile without a sample for
+    /// each vertex starts over from offsets of zero, which looks the same
+    /// since samples that don't fit the tile aren't drawn.
+    pub fn samples_mut(&mut self, tile_size: u32) -> &mut [f32] {
+        let len = (tile_size * tile_size) as usize;
+        let heights = self.heights.get_or_insert_with(|| TileHeights {
+            mode: HeightMode::Offset,
+            samples: Vec::new(),
+        });
+        if heights.samples.len() != len {
+            *heights = TileHeights {
+                mode: HeightMode::Offset,
+                samples: vec![0.0; len],
+            };
+        }
+        &mut heights.samples
+    }
+}
+
+/// How a tile's height samples combine with the procedural height.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeightMode {
+    /// Samples are added to the procedural height.
+    #[default]
+    Offset,
+    /// Samples are the height, the procedural height is ignored.
+    Replace,
+}
+
+/// One height sample per vertex of a tile, `tile_size` by `tile_size`,
+/// stored row by row along x.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileHeights {
+    #[serde(default)]
+    pub mode: HeightMode,
+    pub samples: Vec<f32>,
+}
+
+impl TileHeights {
+    /// Bilinearly interpolates the samples at `local`, a position
+    /// relative to the tile's first vertex. Positions off the tile are
+    /// clamped to its edge.
+    pub fn sample(&self, local: glam::Vec2, tile_size: u32) -> f32 {
+        let max = (tile_size - 1) as f32;
+        let p = local.clamp(glam::Vec2::ZERO, glam::Vec2::splat(max));
+        let p0 = p
+            .floor()
+            .min(glam::Vec2::splat(max - 1.0))
+            .max(glam::Vec2::ZERO);
+        let f = p - p0;
+
+        let (x, z) = (p0.x as u32, p0.y as u32);
+        let x1 = (x + 1).min(tile_size - 1);
+        let z1 = (z + 1).min(tile_size - 1);
+        let at = |x: u32, z: u32| self.samples[(x + z * tile_size) as usize];
+
+        let h0 = at(x, z) + (at(x1, z) - at(x, z)) * f.x;
+        let h1 = at(x, z1) + (at(x1, z1) - at(x, z1)) * f.x;
+        h0 + (h1 - h0) * f.y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(x, z, height, normal)` read back from `terrain_point` in
+    /// `shaders/terrain_common.wgsl` with `tile_size = 32`, `mountains = 100`,
+    /// `dunes = 10`, `spires = 25` and the biomes seed 0 places. The
+    /// normals average the same one-sided differences as `normal_at`,
+    /// evaluated on the GPU. Regenerate this table from a readback whenever the
+    /// shader changes, never from the code it checks.
+    const SHADER_REFERENCE: &[(f32, f32, f32, [f32; 3])] = &[
+        (0.0, 0.0, 9.640753e-6, [1.0750227e-7, 1.0, -4.7293724e-7]),
+        (
+            10.5,
+            20.25,
+            16.798744,
+            [-0.43584448, 0.8950569, 0.094407365],
+        ),
+        (
+            512.0,
+            512.0,
+            7.3871303,
+            [-0.28840157, 0.949562, -0.12311191],
+        ),
+        (
+            600.0,
+            430.0,
+            51.064293,
+            [-0.5120993, 0.6934429, -0.50684446],
+        ),
+        (100.0, 100.0, 7.193711, [0.35862967, 0.90320754, -0.2357989]),
+        (
+            250.75,
+            900.5,
+            44.871998,
+            [-0.48565432, 0.4527896, 0.74774426],
+        ),
+        (-5.0, 3.0, 2.909174, [0.38161418, 0.8947931, -0.23176746]),
+        // Mountains, dunes and a spire
+        (
+            861.75,
+            44.5,
+            63.919415,
+            [0.025766855, 0.64271295, -0.7656737],
+        ),
+        (638.25, 3.0, 6.221735, [-0.21192084, 0.89684236, -0.3882828]),
+        (
+            879.25,
+            475.5,
+            26.381813,
+            [0.26384556, 0.9504018, -0.16468778],
+        ),
+    ];
+
+    fn reference_terrain() -> Terrain {
+        Terrain::generate(32, 32, 100.0, 10.0, 25.0, 0)
+    }
+
+    #[test]
+    fn height_matches_shader() {
+        let terrain = reference_terrain();
+        for &(x, z, height, _) in SHADER_REFERENCE {
+            let actual = terrain.height_at(x, z);
+            assert!(
+                (actual - height).abs() < 1e-2,
+                "height_at({x}, {z}) = {actual}, shader gave {height}"
+            );
+        }
+    }
+
+    #[test]
+    fn normal_matches_shader() {
+        let terrain = reference_terrain();
+        for &(x, z, _, normal) in SHADER_REFERENCE {
+            let expected = glam::Vec3::from(normal).normalize();
+            let actual = terrain.normal_at(x, z);
+            assert!(
+                actual.dot(expected) > 0.999,
+                "normal_at({x}, {z}) = {actual}, shader gave {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn slope_follows_normal() {
+        let terrain = reference_terrain();
+        for &(x, z, _, _) in SHADER_REFERENCE {
+            let slope = terrain.slope_at(x, z);
+            assert!((0.0..=std::f32::consts::FRAC_PI_2).contains(&slope));
+            assert!((slope.cos() - terrain.normal_at(x, z).y).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_terrain() {
+        let points = SHADER_REFERENCE
+            .iter()
+            .map(|&(x, z, _, _)| glam::vec2(x, z));
+        let heights = |seed| {
+            let terrain = Terrain::generate(32, 32, 100.0, 10.0, 25.0, seed);
+            points
+                .clone()
+                .map(|p| terrain.height_at(p.x, p.y).to_bits())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(heights(42), heights(42));
+        assert_ne!(heights(42), heights(43));
+        assert_ne!(heights(42), heights(0));
+    }
+
+    #[test]
+    fn noise_params_shape_terrain() {
+        let mut terrain = reference_terrain();
+        let (x, z) = (861.75, 44.5);
+        let before = terrain.height_at(x, z);
+
+        NoiseParam::MountainOctaves.adjust(&mut terrain.noise, 2);
+        assert_eq!(terrain.noise.mountains.octaves, 6);
+        assert_ne!(terrain.height_at(x, z), before);
+        NoiseParam::MountainOctaves.adjust(&mut terrain.noise, -100);
+        assert_eq!(terrain.noise.mountains.octaves, 1);
+
+        // Heights stay within the bounds culling relies on
+        terrain.noise.mountains.gain = 0.9;
+        terrain.noise.mountains.octaves = 8;
+        let (min, max) = terrain.noise.mountains.range();
+        let s = noise::NoiseSeed::default();
+        for &(x, z, _, _) in SHADER_REFERENCE {
+            let v = noise::fbm(glam::vec2(x, z), &terrain.noise.mountains, s);
+            assert!((min..=max).contains(&v), "fbm({x}, {z}) = {v}");
+        }
+
+        assert_eq!(
+            NoiseParam::SpireCoverage.cycle(1),
+            NoiseParam::MountainOctaves
+        );
+        assert_eq!(
+            NoiseParam::MountainOctaves.cycle(-1),
+            NoiseParam::SpireCoverage
+        );
+    }
+
+    #[test]
+    fn spires_stand_on_the_desert() {
+        let mut terrain = reference_terrain();
+        // On top of a spire
+        let p = glam::vec2(879.25, 475.5);
+        assert!(terrain.biome_blend(p).z > 0.9);
+        let with_spires = terrain.height_at(p.x, p.y);
+
+        // Without spires the same point is desert floor
+        terrain.spire_height = 0.0;
+        let floor = terrain.height_at(p.x, p.y);
+        assert!(with_spires > floor + 10.0);
+        terrain.spire_height = 25.0;
+        terrain.noise.spires.coverage = 0.0;
+        assert_eq!(terrain.biome_blend(p).z, 0.0);
+        assert!((terrain.height_at(p.x, p.y) - floor).abs() < 1e-3);
+
+        // Never taller than the spire height
+        let params = SpireNoise::default();
+        let s = noise::NoiseSeed::default();
+        for i in 0..1000 {
+            let v = noise::spire_columns(glam::vec2(i as f32 * 1.37, i as f32 * 0.61), &params, s);
+            assert!((0.0..=1.0).contains(&v), "spire_columns = {v}");
+        }
+    }
+
+    #[test]
+    fn pick_finds_tile_and_normal() {
+        let terrain = reference_terrain();
+        let (x, z) = (31.0 * 3.0 + 10.5, 20.25);
+        let hit = terrain
+            .pick(glam::vec3(x, 500.0, z), glam::Vec3::NEG_Y, 1000.0)
+            .unwrap();
+        assert_eq!(hit.tile, (3, 0));
+        assert!((hit.position - glam::vec3(x, terrain.height_at(x, z), z)).length() < 1e-2);
+        assert!(hit.normal.dot(terrain.normal_at(x, z)) > 0.999);
+
+        // Rays that miss, or only hit the ground past the edge, find nothing
+        let up = terrain.pick(glam::vec3(x, 500.0, z), glam::Vec3::Y, 1000.0);
+        assert!(up.is_none());
+        let off_edge = terrain.pick(glam::vec3(-10.0, 500.0, z), glam::Vec3::NEG_Y, 1000.0);
+        assert!(off_edge.is_none());
+        assert_eq!(terrain.tile_at(31.0 * 32.0, 0.0), Some((31, 0)));
+    }
+
+    #[test]
+    fn tile_heights_offset_or_replace() {
+        let mut terrain = reference_terrain();
+        let n = terrain.tile_size;
+        let samples = (0..n * n).map(|i| (i % n) as f32).collect::<Vec<_>>();
+        let (x, z) = (31.0 + 10.5, 4.0);
+        let procedural = terrain.height_at(x, z);
+
+        terrain.tiles[1].heights = Some(TileHeights {
+            mode: HeightMode::Offset,
+            samples: samples.clone(),
+        });
+        assert!((terrain.height_at(x, z) - (procedural + 10.5)).abs() < 1e-4);
+
+        terrain.tiles[1].heights = Some(TileHeights {
+            mode: HeightMode::Replace,
+            samples,
+        });
+        assert!((terrain.height_at(x, z) - 10.5).abs() < 1e-4);
+
+        // Samples of the wrong size are ignored
+        terrain.tiles[1].heights.as_mut().unwrap().samples.pop();
+        assert_eq!(terrain.height_at(x, z), procedural);
+    }
+
+    #[test]
+    fn tile_heights_round_trip() {
+        let mut terrain = Terrain::generate(2, 4, 100.0, 10.0, 25.0, 0);
+        terrain.tiles[3].heights = Some(TileHeights {
+            mode: HeightMode::Replace,
+            samples: (0..16).map(|i| i as f32 * 0.1 - 0.3).collect(),
+        });
+
+        let json = serde_json::to_string(&terrain).unwrap();
+        let loaded: Terrain = serde_json::from_str(&json).unwrap();
+        assert!(loaded.tiles[..3].iter().all(|tile| tile.heights.is_none()));
+        assert_eq!(loaded.tiles[3].heights, terrain.tiles[3].heights);
+
+        // Files saved before tiles had heights still load
+        let old: Terrain = serde_json::from_str(
+            r#"{"mountain_height":1,"dune_height":1,"spire_height":1,"size":1,"tile_size":2,"tiles":[{"id":[0,0]}]}"#,
+        )
+        .unwrap();
+        assert!(old.tiles[0].heights.is_none());
+    }
+}
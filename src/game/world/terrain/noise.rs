@@ -0,0 +1,157 @@
+//! CPU ports of the noise functions in `shaders/terrain_common.wgsl`.
+//!
+//! These are written to follow the WGSL line by line so that the heights
+//! computed here match what the GPU draws. If you change one, change the other.
+
+// Keep the literals exactly as they appear in the shader.
+#![allow(clippy::excessive_precision)]
+
+use glam::{Vec2, Vec3, Vec4};
+
+use crate::game::world::terrain::{FbmNoise, SpireNoise};
+
+/// What a terrain's seed changes about the noise. Seed 0 leaves the noise
+/// as it was before terrains had seeds.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NoiseSeed {
+    /// Added to the simplex lattice before it is permuted.
+    pub offset: Vec2,
+    /// Added to the argument of `sin` in `hash`.
+    pub phase: f32,
+}
+
+impl NoiseSeed {
+    pub fn new(seed: u32) -> Self {
+        if seed == 0 {
+            return Self::default();
+        }
+
+        let h0 = pcg(seed);
+        let h1 = pcg(h0);
+        let h2 = pcg(h1);
+        Self {
+            offset: glam::vec2((h0 % 289) as f32, (h1 % 289) as f32),
+            phase: (h2 >> 8) as f32 / 16777216.0 * 6.2831855,
+        }
+    }
+}
+
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+pub fn hash(p: Vec2, s: NoiseSeed) -> f32 {
+    fract((p.dot(glam::vec2(12.9898, 78.233)) + s.phase).sin() * 43758.5453123)
+}
+
+pub fn fbm(p: Vec2, params: &FbmNoise, s: NoiseSeed) -> f32 {
+    let mut x = p * params.frequency;
+    let mut v = 0.0;
+    let mut a = 0.5;
+    let shift = Vec2::splat(100.0);
+    let cs = glam::vec2(params.rotation.cos(), params.rotation.sin());
+    let rot = glam::Mat2::from_cols(glam::vec2(cs.x, cs.y), glam::vec2(-cs.y, cs.x));
+
+    for _ in 0..params.octaves {
+        v += a * snoise2(x, s);
+        x = rot * x * params.lacunarity + shift;
+        a *= params.gain;
+    }
+
+    v * 0.5 + 0.5
+}
+
+pub fn smooth_voronoi(x: Vec2, s: NoiseSeed) -> f32 {
+    let p = x.floor();
+    let f = x.fract_gl();
+
+    let mut res = 0.0;
+    for j in -1..=1 {
+        for i in -1..=1 {
+            let b = glam::vec2(i as f32, j as f32);
+            let r = b - f + hash(p + b, s);
+            let d = r.dot(r);
+
+            res += 1.0 / d.powf(8.0);
+        }
+    }
+
+    (1.0 / res).powf(1.0 / 16.0)
+}
+
+pub fn spire_columns(p: Vec2, params: &SpireNoise, s: NoiseSeed) -> f32 {
+    let x = p * params.frequency;
+    let g = x.floor();
+    let f = x.fract_gl();
+    let rough = snoise2(x * 8.0, s) * params.roughness;
+
+    let mut h: f32 = 0.0;
+    for j in -1..=1 {
+        for i in -1..=1 {
+            let b = glam::vec2(i as f32, j as f32);
+            let cell = g + b;
+            let center = b + 0.25 + 0.5 * glam::vec2(hash(cell, s), hash(cell + 17.0, s));
+            let size = params.radius * (0.5 + 0.5 * hash(cell + 31.0, s));
+            let top = 0.4 + 0.6 * hash(cell + 53.0, s);
+            let d = (center - f).length() + rough;
+
+            h = h.max((1.0 - smoothstep(size * 0.7, size, d)) * top);
+        }
+    }
+
+    let steps = (params.terraces as f32).max(1.0);
+    let t = h * steps;
+    (t.floor() + smoothstep(0.6, 1.0, fract(t))) / steps
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn permute3(x: Vec3) -> Vec3 {
+    (((x * 34.0) + 1.0) * x) % Vec3::splat(289.0)
+}
+
+pub fn snoise2(v: Vec2, s: NoiseSeed) -> f32 {
+    let c = Vec4::new(
+        0.211324865405187,
+        0.366025403784439,
+        -0.577350269189626,
+        0.024390243902439,
+    );
+    let mut i = (v + v.dot(Vec2::splat(c.y))).floor();
+    let x0 = v - i + i.dot(Vec2::splat(c.x));
+    let i1 = if x0.x > x0.y {
+        glam::vec2(1.0, 0.0)
+    } else {
+        glam::vec2(0.0, 1.0)
+    };
+    let x12 = Vec4::new(x0.x, x0.y, x0.x, x0.y) + Vec4::new(c.x, c.x, c.z, c.z)
+        - Vec4::new(i1.x, i1.y, 0.0, 0.0);
+    i = (i + s.offset) % Vec2::splat(289.0);
+    let p = permute3(permute3(i.y + glam::vec3(0.0, i1.y, 1.0)) + i.x + glam::vec3(0.0, i1.x, 1.0));
+    let x12_xy = glam::vec2(x12.x, x12.y);
+    let x12_zw = glam::vec2(x12.z, x12.w);
+    let mut m = (Vec3::splat(0.5) - glam::vec3(x0.dot(x0), x12_xy.dot(x12_xy), x12_zw.dot(x12_zw)))
+        .max(Vec3::ZERO);
+    m *= m;
+    m *= m;
+    let x = 2.0 * (p * c.w).fract_gl() - 1.0;
+    let h = x.abs() - 0.5;
+    let ox = (x + 0.5).floor();
+    let a0 = x - ox;
+    m *= 1.79284291400159 - 0.85373472095314 * (a0 * a0 + h * h);
+    let g = glam::vec3(
+        a0.x * x0.x + h.x * x0.y,
+        a0.y * x12.x + h.y * x12.y,
+        a0.z * x12.z + h.z * x12.w,
+    );
+    130.0 * m.dot(g)
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
@@ -0,0 +1,405 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::world::terrain::{
+    Terrain, dunes,
+    heightfield::{HeightChanges, HeightField, NEIGHBOURS, Rng},
+    mountains, noise, spires,
+};
+
+/// Furthest upwind, in world units, [`blow_sand`] looks for a dune
+/// sheltering a vertex from the wind.
+const SHADOW_REACH: u32 = 24;
+/// Settings for [`blow_sand`], which moves sand around the dunes the way
+/// Werner's model of dune fields does. The wind picks up slabs of sand
+/// and carries them downwind in hops until they land, more often on sand
+/// than on bare ground. Behind dunes the wind can't reach, slabs always
+/// land and are never picked up, and slopes steeper than sand can hold
+/// slide down. Over many passes this grows barchans and ridges across
+/// the wind that creep downwind.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SandOptions {
+    /// Direction the wind blows towards, in degrees from the x axis
+    /// towards the z axis.
+    pub wind_direction: f32,
+    /// How many times, on average, the wind tries to pick up sand from
+    /// each vertex in one pass.
+    pub iterations: u32,
+    /// Height of one slab of sand.
+    pub slab_height: f32,
+    /// How far the wind carries a slab in one hop, in world units.
+    pub hop_length: f32,
+    /// Chance a slab lands where there's sand, from 0 to 1.
+    pub sand_deposition: f32,
+    /// Chance a slab lands on bare ground, from 0 to 1.
+    pub bare_deposition: f32,
+    /// Depth of the sand under the procedural dunes, on ground that's all
+    /// dunes. Nothing under it can be blown away.
+    pub sand_depth: f32,
+    /// Slope, in degrees, of the shadow dunes cast downwind.
+    pub shadow_angle: f32,
+    /// Steepest slope, in degrees, sand stays on without sliding down.
+    pub repose_angle: f32,
+}
+
+impl Default for SandOptions {
+    fn default() -> Self {
+        Self {
+            wind_direction: 0.0,
+            iterations: 5,
+            slab_height: 0.2,
+            hop_length: 3.0,
+            sand_deposition: 0.6,
+            bare_deposition: 0.4,
+            sand_depth: 2.0,
+            shadow_angle: 15.0,
+            repose_angle: 33.0,
+        }
+    }
+}
+
+impl SandOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("sand_deposition", self.sand_deposition),
+            ("bare_deposition", self.bare_deposition),
+        ] {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&value),
+                "{name} must be from 0 to 1, not {value}"
+            );
+        }
+        for (name, value) in [
+            ("shadow_angle", self.shadow_angle),
+            ("repose_angle", self.repose_angle),
+        ] {
+            anyhow::ensure!(
+                value > 0.0 && value < 90.0,
+                "{name} must be between 0 and 90 degrees, not {value}"
+            );
+        }
+        for (name, value) in [
+            ("slab_height", self.slab_height),
+            ("hop_length", self.hop_length),
+        ] {
+            anyhow::ensure!(
+                value.is_finite() && value > 0.0,
+                "{name} must be positive, not {value}"
+            );
+        }
+        // Any taller and a slab sliding down a slope leaves it steeper the
+        // other way, so it slides back forever
+        anyhow::ensure!(
+            self.slab_height < self.repose_angle.to_radians().tan(),
+            "slab_height must be less than the slope of repose_angle, {}, not {}",
+            self.repose_angle.to_radians().tan(),
+            self.slab_height
+        );
+        anyhow::ensure!(
+            self.wind_direction.is_finite(),
+            "wind_direction must be a number, not {}",
+            self.wind_direction
+        );
+        anyhow::ensure!(
+            self.sand_depth.is_finite() && self.sand_depth >= 0.0,
+            "sand_depth can't be negative, not {}",
+            self.sand_depth
+        );
+        Ok(())
+    }
+
+    /// Unit vector the wind blows along.
+    pub fn wind(&self) -> glam::Vec2 {
+        glam::Vec2::from_angle(self.wind_direction.to_radians())
+    }
+}
+
+/// Blows the sand on the terrain's heights as they're baked, without
+/// changing the terrain. Sand blown off the edge of the terrain is lost.
+/// The same seed moves the same slabs.
+pub fn blow_sand(terrain: &Terrain, options: &SandOptions, seed: u32) -> HeightChanges {
+    let original = HeightField::bake(terrain);
+    let width = original.width;
+    let slabs = (0..width * width)
+        .map(|i| {
+            let p = glam::vec2((i % width) as f32, (i / width) as f32);
+            let sand = original.heights[i as usize] - terrain.bedrock_at(p, options.sand_depth);
+            // Saturates to no slabs where there's no sand
+            (sand / options.slab_height) as u32
+        })
+        .collect();
+
+    let mut field = SandField {
+        field: original.clone(),
+        slabs,
+        options: *options,
+        wind: options.wind(),
+        shadow_slope: options.shadow_angle.to_radians().tan(),
+        repose_slope: options.repose_angle.to_radians().tan(),
+    };
+    let mut rng = Rng::new(seed);
+    let vertices = (width * width) as usize;
+    for _ in 0..options.iterations as usize * vertices {
+        let i = ((rng.next_f32() * vertices as f32) as usize).min(vertices - 1);
+        field.blow(i, &mut rng);
+    }
+
+    field.field.changes(&original, terrain)
+}
+
+impl Terrain {
+    /// Height of the ground under the sand at `p`. It's the procedural
+    /// height without the dunes, `sand_depth` lower where there are
+    /// dunes, and doesn't change with sculpting, so many passes of
+    /// [`blow_sand`] never dig deeper than one. Off the dunes, nothing is
+    /// sand.
+    fn bedrock_at(&self, p: glam::Vec2, sand_depth: f32) -> f32 {
+        let blend = self.biome_blend(p);
+        if blend.y <= 0.0 {
+            return f32::INFINITY;
+        }
+
+        let s = noise::NoiseSeed::new(self.seed);
+        let y0 = mountains(p, &self.noise.mountains, self.mountain_height, s);
+        let y1 = dunes(p, &self.noise.dunes, self.dune_height, s);
+        let y2 = spires(p, &self.noise.spires, self.spire_height, s);
+        // Like `point`, but the dunes are all sand
+        y0 * blend.x + y1 * blend.z + y2 * blend.z - sand_depth * blend.y
+    }
+}
+
+struct SandField {
+    field: HeightField,
+    /// Slabs of sand on each vertex, which are all that can be moved.
+    slabs: Vec<u32>,
+    options: SandOptions,
+    wind: glam::Vec2,
+    shadow_slope: f32,
+    repose_slope: f32,
+}
+
+impl SandField {
+    fn position(&self, i: usize) -> glam::Vec2 {
+        let width = self.field.width as usize;
+        glam::vec2((i % width) as f32, (i / width) as f32)
+    }
+
+    /// Vertex nearest to `p`, if it's on the terrain.
+    fn vertex(&self, p: glam::Vec2) -> Option<usize> {
+        let (x, z) = (p.x.round() as i32, p.y.round() as i32);
+        self.field.contains(x, z).then(|| self.field.index(x, z))
+    }
+
+    /// Picks up a slab from vertex `i`, if it has one and is out of the
+    /// shadows, and hops it downwind until it lands.
+    fn blow(&mut self, i: usize, rng: &mut Rng) {
+        if self.slabs[i] == 0 || self.in_shadow(i) {
+            return;
+        }
+        self.take(i);
+        self.slide_into(i);
+
+        let mut p = self.position(i);
+        loop {
+            p += self.wind * self.options.hop_length;
+            let Some(j) = self.vertex(p) else {
+                // Blown off the terrain
+                return;
+            };
+            let chance = if self.slabs[j] > 0 {
+                self.options.sand_deposition
+            } else {
+                self.options.bare_deposition
+            };
+            if self.in_shadow(j) || rng.next_f32() < chance {
+                self.put(j);
+                self.slide_from(j);
+                return;
+            }
+        }
+    }
+
+    /// Whether a dune upwind of vertex `i` shelters it from the wind.
+    fn in_shadow(&self, i: usize) -> bool {
+        let p = self.position(i);
+        let height = self.field.heights[i];
+        for d in 1..=SHADOW_REACH {
+            let d = d as f32;
+            let Some(j) = self.vertex(p - self.wind * d) else {
+                return false;
+            };
+            if self.field.heights[j] - height > d * self.shadow_slope {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn take(&mut self, i: usize) {
+        self.slabs[i] -= 1;
+        self.field.heights[i] -= self.options.slab_height;
+    }
+
+    fn put(&mut self, i: usize) {
+        self.slabs[i] += 1;
+        self.field.heights[i] += self.options.slab_height;
+    }
+
+    /// Neighbour of vertex `i` with the steepest slope `slope` picks out,
+    /// if it's steeper than sand holds.
+    fn steepest(&self, i: usize, slope: impl Fn(usize, f32) -> Option<f32>) -> Option<usize> {
+        let p = self.position(i);
+        NEIGHBOURS
+            .iter()
+            .filter_map(|&(dx, dz, distance)| {
+                let j = self.vertex(p + glam::vec2(dx as f32, dz as f32))?;
+                Some((j, slope(j, distance)?))
+            })
+            .filter(|&(_, s)| s > self.repose_slope)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(j, _)| j)
+    }
+
+    /// Slides the slab on top of vertex `i` downhill until it rests. A
+    /// slab can't slide further than there are vertices.
+    fn slide_from(&mut self, mut i: usize) {
+        for _ in 0..self.slabs.len() {
+            if self.slabs[i] == 0 {
+                return;
+            }
+            let heights = &self.field.heights;
+            let Some(j) = self.steepest(i, |j, d| Some((heights[i] - heights[j]) / d)) else {
+                return;
+            };
+            self.take(i);
+            self.put(j);
+            i = j;
+        }
+    }
+
+    /// Slides sand from the slopes above vertex `i` into it until they
+    /// rest, or it has slid as far as there are vertices.
+    fn slide_into(&mut self, mut i: usize) {
+        for _ in 0..self.slabs.len() {
+            let (heights, slabs) = (&self.field.heights, &self.slabs);
+            let Some(j) = self.steepest(i, |j, d| {
+                (slabs[j] > 0).then(|| (heights[j] - heights[i]) / d)
+            }) else {
+                return;
+            };
+            self.take(j);
+            self.put(i);
+            i = j;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::terrain::{biome::Biome, heightfield::tests::fixture};
+
+    #[test]
+    fn wind_blows_sand_downwind() {
+        for (wind_direction, upwind, downwind) in [(0.0, 0..8, 57..65), (180.0, 57..65, 0..8)] {
+            let mut terrain = fixture(2, 33, Biome::Dunes, |_, _| 0.0);
+            let options = SandOptions {
+                wind_direction,
+                iterations: 2,
+                sand_depth: 1.0,
+                ..Default::default()
+            };
+            for seed in 0..2 {
+                blow_sand(&terrain, &options, seed).apply(&mut terrain);
+            }
+
+            let change = |xs: std::ops::Range<u32>| {
+                let mut sum = 0.0;
+                for z in 0..=64 {
+                    for x in xs.clone() {
+                        let height = terrain.height_at(x as f32, z as f32);
+                        // Sand is never dug out deeper than it was, even
+                        // over many passes
+                        assert!(height >= -1.0 - 1e-4);
+                        sum += height;
+                    }
+                }
+                sum
+            };
+            // Nothing blows onto the upwind edge to replace what's taken
+            assert!(change(upwind.clone()) < 0.0, "wind {wind_direction}");
+            assert!(change(upwind) < change(downwind), "wind {wind_direction}");
+        }
+    }
+
+    #[test]
+    fn rejects_slabs_taller_than_the_repose_slope() {
+        let options = SandOptions {
+            slab_height: 1.0,
+            repose_angle: 33.0,
+            ..Default::default()
+        };
+        let error = options.validate().unwrap_err().to_string();
+        assert!(error.contains("slab_height"), "{error}");
+
+        // Sliding still stops if they're used anyway
+        let options = SandOptions {
+            sand_depth: 5.0,
+            iterations: 1,
+            ..options
+        };
+        let terrain = fixture(1, 17, Biome::Dunes, |_, _| 0.0);
+        let changes = blow_sand(&terrain, &options, 1);
+        let deltas = changes.tiles.iter().flat_map(|(_, deltas)| deltas);
+        assert!(deltas.clone().all(|delta| delta.is_finite()));
+        // Whole slabs are only ever lost off the edge
+        let total: f32 = deltas.sum();
+        assert!(
+            total <= 1e-3 && (total - total.round()).abs() < 1e-3,
+            "{total}"
+        );
+
+        let options = SandOptions {
+            slab_height: 1.0,
+            repose_angle: 46.0,
+            ..Default::default()
+        };
+        options.validate().unwrap();
+    }
+
+    #[test]
+    fn crest_moves_downwind() {
+        // A cone of sand on bare ground
+        let mut terrain = fixture(2, 33, Biome::Dunes, |x, z| {
+            let d = glam::vec2(x as f32 - 16.0, z as f32 - 32.0).length();
+            (4.0 - d * 0.5).max(0.0)
+        });
+        let options = SandOptions {
+            sand_depth: 0.0,
+            ..Default::default()
+        };
+        let crest = |terrain: &Terrain| {
+            let vertices = (0..=64u32).flat_map(|z| (0..=64u32).map(move |x| (x, z)));
+            let height = |&(x, z): &(u32, u32)| terrain.height_at(x as f32, z as f32);
+            let top = vertices.max_by(|a, b| height(a).total_cmp(&height(b)));
+            top.unwrap().0
+        };
+
+        let start = crest(&terrain);
+        let mut last = start;
+        for seed in 0..6 {
+            blow_sand(&terrain, &options, seed).apply(&mut terrain);
+            let x = crest(&terrain);
+            assert!(x >= last, "crest went back upwind from {last} to {x}");
+            last = x;
+        }
+        assert!(last >= start + 8, "crest only moved from {start} to {last}");
+    }
+
+    #[test]
+    fn only_dunes_have_sand() {
+        let terrain = fixture(2, 33, Biome::Mountains, |_, _| 0.0);
+        let changes = blow_sand(&terrain, &SandOptions::default(), 1);
+        assert!(changes.tiles.is_empty());
+    }
+}
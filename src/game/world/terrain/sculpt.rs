@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+
+use crate::game::world::terrain::{
+    Terrain, TerrainTile,
+    biome::{Biome, BiomeWeights},
+    noise,
+};
+
+/// Most strokes that can be undone.
+const MAX_UNDO_STEPS: usize = 64;
+/// Scales `Brush::strength` into the fraction of the way smooth and
+/// flatten move a vertex to their target each second.
+const RELAX_RATE: f32 = 0.1;
+/// Frequency of the pattern the noise brush paints.
+const NOISE_FREQUENCY: f32 = 0.15;
+const MIN_BRUSH_RADIUS: f32 = 1.0;
+const MAX_BRUSH_RADIUS: f32 = 128.0;
+const MIN_BRUSH_STRENGTH: f32 = 0.1;
+const MAX_BRUSH_STRENGTH: f32 = 1000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushKind {
+    Raise,
+    Lower,
+    /// Pulls each vertex towards the average of its neighbours.
+    Smooth,
+    /// Pulls each vertex towards the height where the stroke started.
+    Flatten,
+    Noise,
+    /// Moves the biome weights of the tiles under the brush towards only
+    /// having this biome.
+    Paint(Biome),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Brush {
+    pub kind: BrushKind,
+    /// In world units.
+    pub radius: f32,
+    /// Height per second at the centre of the brush.
+    pub strength: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            kind: BrushKind::Raise,
+            radius: 8.0,
+            strength: 10.0,
+        }
+    }
+}
+
+impl Brush {
+    pub fn scale_radius(&mut self, factor: f32) {
+        self.radius = (self.radius * factor).clamp(MIN_BRUSH_RADIUS, MAX_BRUSH_RADIUS);
+    }
+
+    pub fn scale_strength(&mut self, factor: f32) {
+        self.strength = (self.strength * factor).clamp(MIN_BRUSH_STRENGTH, MAX_BRUSH_STRENGTH);
+    }
+}
+
+/// A tile before and after a stroke.
+#[derive(Debug)]
+struct TileEdit {
+    before: TerrainTile,
+    after: TerrainTile,
+}
+
+#[derive(Debug)]
+struct Stroke {
+    /// Every tile the stroke touched, from before it started.
+    before: HashMap<(u32, u32), TerrainTile>,
+    flatten_height: f32,
+}
+
+/// Applies brushes to a terrain's tile heights and keeps the history of
+/// strokes for undo and redo.
+#[derive(Debug, Default)]
+pub struct Sculptor {
+    pub brush: Brush,
+    stroke: Option<Stroke>,
+    undo: Vec<Vec<TileEdit>>,
+    redo: Vec<Vec<TileEdit>>,
+}
+
+impl Sculptor {
+    /// Applies the brush at `center` for `dt` seconds, starting a stroke
+    /// if there isn't one. Returns the tiles that changed.
+    pub fn dab(&mut self, terrain: &mut Terrain, center: glam::Vec2, dt: f32) -> Vec<(u32, u32)> {
+        let stroke = self.stroke.get_or_insert_with(|| Stroke {
+            before: HashMap::new(),
+            flatten_height: terrain.height_at(center.x, center.y),
+        });
+
+        let brush = self.brush;
+        if let BrushKind::Paint(biome) = brush.kind {
+            return paint(stroke, terrain, brush, biome, center, dt);
+        }
+
+        // Work out every change before making any, so each vertex is
+        // moved based on the terrain as it was before this dab
+        let seed = noise::NoiseSeed::new(terrain.seed);
+        let max = (terrain.size * (terrain.tile_size - 1)) as f32;
+        let min = (center - brush.radius).ceil().max(glam::Vec2::ZERO);
+        let max = (center + brush.radius).floor().min(glam::Vec2::splat(max));
+        let mut deltas = Vec::new();
+        for z in min.y as u32..=max.y as u32 {
+            for x in min.x as u32..=max.x as u32 {
+                let p = glam::vec2(x as f32, z as f32);
+                let distance = p.distance(center);
+                if distance > brush.radius {
+                    continue;
+                }
+
+                let falloff = (1.0 - (distance / brush.radius).powi(2)).powi(2);
+                let amount = brush.strength * falloff * dt;
+                let relax = (amount * RELAX_RATE).min(1.0);
+                let height = terrain.height_at(p.x, p.y);
+                let delta = match brush.kind {
+                    BrushKind::Raise => amount,
+                    BrushKind::Lower => -amount,
+                    BrushKind::Smooth => {
+                        let neighbours = [(-1.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.0, 1.0)]
+                            .map(|(dx, dz)| terrain.height_at(p.x + dx, p.y + dz));
+                        (neighbours.iter().sum::<f32>() * 0.25 - height) * relax
+                    }
+                    BrushKind::Flatten => (stroke.flatten_height - height) * relax,
+                    BrushKind::Noise => noise::snoise2(p * NOISE_FREQUENCY, seed) * amount,
+                    // Painting leaves the heights alone
+                    BrushKind::Paint(_) => continue,
+                };
+                deltas.push(((x, z), delta));
+            }
+        }
+
+        let mut changed = Vec::new();
+        for ((x, z), delta) in deltas {
+            for (id, local) in vertex_tiles(terrain, x, z) {
+                let tile_size = terrain.tile_size;
+                let Some(tile) = terrain.tile_mut(id) else {
+                    continue;
+                };
+                stroke.before.entry(id).or_insert_with(|| tile.clone());
+
+                tile.samples_mut(tile_size)[(local.0 + local.1 * tile_size) as usize] += delta;
+
+                if !changed.contains(&id) {
+                    changed.push(id);
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// Finishes the current stroke, making it one undo step.
+    pub fn end_stroke(&mut self, terrain: &Terrain) {
+        let Some(stroke) = self.stroke.take() else {
+            return;
+        };
+
+        let edits = stroke
+            .before
+            .into_iter()
+            .filter_map(|(id, before)| {
+                Some(TileEdit {
+                    before,
+                    after: terrain.tile(id)?.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        if edits.is_empty() {
+            return;
+        }
+
+        self.undo.push(edits);
+        if self.undo.len() > MAX_UNDO_STEPS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// Makes a change that doesn't come from the brush, like erosion, one
+    /// undo step of its own. `edit` returns the tiles it changed, which
+    /// are passed on.
+    pub fn edit(
+        &mut self,
+        terrain: &mut Terrain,
+        edit: impl FnOnce(&mut Terrain) -> Vec<(u32, u32)>,
+    ) -> Vec<(u32, u32)> {
+        self.end_stroke(terrain);
+        let tiles = terrain.tiles.clone();
+        let changed = edit(terrain);
+
+        let before = tiles
+            .into_iter()
+            .filter(|tile| changed.contains(&tile.id))
+            .map(|tile| (tile.id, tile))
+            .collect();
+        self.stroke = Some(Stroke {
+            before,
+            flatten_height: 0.0,
+        });
+        self.end_stroke(terrain);
+        changed
+    }
+
+    /// Reverts the last stroke. Returns the tiles that changed.
+    pub fn undo(&mut self, terrain: &mut Terrain) -> Vec<(u32, u32)> {
+        self.end_stroke(terrain);
+        let Some(edits) = self.undo.pop() else {
+            return Vec::new();
+        };
+
+        let changed = restore(terrain, &edits, |edit| &edit.before);
+        self.redo.push(edits);
+        changed
+    }
+
+    /// Reapplies the last stroke that was undone. Returns the tiles that
+    /// changed.
+    pub fn redo(&mut self, terrain: &mut Terrain) -> Vec<(u32, u32)> {
+        self.end_stroke(terrain);
+        let Some(edits) = self.redo.pop() else {
+            return Vec::new();
+        };
+
+        let changed = restore(terrain, &edits, |edit| &edit.after);
+        self.undo.push(edits);
+        changed
+    }
+}
+
+fn restore(
+    terrain: &mut Terrain,
+    edits: &[TileEdit],
+    state: impl Fn(&TileEdit) -> &TerrainTile,
+) -> Vec<(u32, u32)> {
+    edits
+        .iter()
+        .filter_map(|edit| {
+            let state = state(edit);
+            let tile = terrain.tile_mut(state.id)?;
+            *tile = state.clone();
+            Some(state.id)
+        })
+        .collect()
+}
+
+/// Paints every tile the brush reaches, by how close the brush is to the
+/// tile's centre. The tile under the brush is always painted.
+fn paint(
+    stroke: &mut Stroke,
+    terrain: &mut Terrain,
+    brush: Brush,
+    biome: Biome,
+    center: glam::Vec2,
+    dt: f32,
+) -> Vec<(u32, u32)> {
+    let span = terrain.tile_span();
+    let reach = brush.radius + span * std::f32::consts::FRAC_1_SQRT_2;
+    let target = BiomeWeights::only(biome).to_vec3();
+
+    let mut changed = Vec::new();
+    for tile in &mut terrain.tiles {
+        let tile_center = (glam::vec2(tile.id.0 as f32, tile.id.1 as f32) + 0.5) * span;
+        let distance = tile_center.distance(center);
+        if distance > reach {
+            continue;
+        }
+
+        let falloff = (1.0 - (distance / reach).powi(2)).powi(2);
+        let amount = (brush.strength * falloff * dt * RELAX_RATE).min(1.0);
+        let weights = tile.biome.to_vec3();
+        if amount <= 0.0 || weights == target {
+            continue;
+        }
+
+        stroke.before.entry(tile.id).or_insert_with(|| tile.clone());
+        tile.biome = BiomeWeights::from_vec3(weights.lerp(target, amount));
+        changed.push(tile.id);
+    }
+    changed
+}
+
+/// Every tile that has a vertex at `(x, z)`, with the vertex's position
+/// in that tile. Vertices on tile edges are shared by up to four tiles.
+fn vertex_tiles(terrain: &Terrain, x: u32, z: u32) -> Vec<((u32, u32), (u32, u32))> {
+    let span = terrain.tile_size - 1;
+    let candidates = |v: u32| {
+        let tile = v / span;
+        let mut tiles = vec![(tile, v - tile * span)];
+        if v.is_multiple_of(span) && tile > 0 {
+            tiles.push((tile - 1, span));
+        }
+        tiles.retain(|&(tile, _)| tile < terrain.size);
+        tiles
+    };
+
+    let mut tiles = Vec::new();
+    for (tz, lz) in candidates(z) {
+        for &(tx, lx) in &candidates(x) {
+            tiles.push(((tx, tz), (lx, lz)));
+        }
+    }
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terrain() -> Terrain {
+        Terrain::generate(2, 9, 100.0, 10.0, 25.0, 0)
+    }
+
+    #[test]
+    fn raise_keeps_shared_edges_together() {
+        let mut terrain = terrain();
+        let before = terrain.height_at(8.0, 8.0);
+
+        let mut sculptor = Sculptor::default();
+        let changed = sculptor.dab(&mut terrain, glam::vec2(8.0, 8.0), 0.5);
+        assert_eq!(changed.len(), 4);
+
+        // The centre is a corner of all four tiles, and each moved it by
+        // strength * dt
+        assert!((terrain.height_at(8.0, 8.0) - (before + 5.0)).abs() < 1e-3);
+        for id in changed {
+            let heights = terrain.tile(id).unwrap().heights.as_ref().unwrap();
+            let local = (8 - id.0 * 8, 8 - id.1 * 8);
+            assert!((heights.samples[(local.0 + local.1 * 9) as usize] - 5.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn stroke_is_one_undo_step() {
+        let mut terrain = terrain();
+        let original = terrain.height_at(4.0, 4.0);
+
+        let mut sculptor = Sculptor::default();
+        sculptor.brush.kind = BrushKind::Lower;
+        sculptor.brush.radius = 3.0;
+        for _ in 0..3 {
+            sculptor.dab(&mut terrain, glam::vec2(4.0, 4.0), 0.1);
+        }
+        sculptor.end_stroke(&terrain);
+        let sculpted = terrain.height_at(4.0, 4.0);
+        assert!((sculpted - (original - 3.0)).abs() < 1e-3);
+
+        assert_eq!(sculptor.undo(&mut terrain), [(0, 0)]);
+        assert_eq!(terrain.height_at(4.0, 4.0), original);
+        assert!(terrain.tiles[0].heights.is_none());
+
+        sculptor.redo(&mut terrain);
+        assert_eq!(terrain.height_at(4.0, 4.0), sculpted);
+    }
+
+    #[test]
+    fn paint_moves_biomes_and_undoes() {
+        let mut terrain = terrain();
+        let before = terrain.tiles.clone();
+
+        let mut sculptor = Sculptor::default();
+        sculptor.brush = Brush {
+            kind: BrushKind::Paint(Biome::Spires),
+            radius: 1.0,
+            strength: 5.0,
+        };
+        // Over the centre of tile (1, 0), which is 8 from the nearest
+        // other centres, out of reach of a brush this small
+        let changed = sculptor.dab(&mut terrain, glam::vec2(12.0, 4.0), 1.0);
+        assert_eq!(changed, [(1, 0)]);
+        let painted = terrain.tiles[1].biome.to_vec3();
+        assert_eq!(painted, before[1].biome.to_vec3().lerp(glam::Vec3::Z, 0.5));
+        sculptor.dab(&mut terrain, glam::vec2(12.0, 4.0), 10.0);
+        assert_eq!(terrain.tiles[1].biome, BiomeWeights::only(Biome::Spires));
+
+        sculptor.undo(&mut terrain);
+        assert_eq!(terrain.tiles[1].biome, before[1].biome);
+        assert!(terrain.tiles[1].heights.is_none());
+    }
+
+    #[test]
+    fn flatten_moves_towards_start_height() {
+        let mut terrain = terrain();
+        let target = terrain.height_at(6.0, 6.0);
+
+        let mut sculptor = Sculptor::default();
+        sculptor.brush = Brush {
+            kind: BrushKind::Flatten,
+            radius: 4.0,
+            strength: 1000.0,
+        };
+        sculptor.dab(&mut terrain, glam::vec2(6.0, 6.0), 1.0);
+        assert!((terrain.height_at(7.0, 6.0) - target).abs() < 1e-3);
+    }
+}
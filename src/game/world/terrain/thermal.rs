@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+
+use crate::game::world::terrain::{
+    Terrain,
+    heightfield::{HeightChanges, HeightField, NEIGHBOURS, Rng},
+};
+
+/// Settings for [`weather`], which crumbles slopes steeper than the
+/// talus angle of their biome and piles the material at their feet, the
+/// way scree collects under cliffs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThermalOptions {
+    /// How many times every vertex sheds material downhill.
+    pub iterations: u32,
+    /// Fraction of the material over the talus angle moved each
+    /// iteration, from 0 to 0.5. Any more and vertices overshoot.
+    pub rate: f32,
+    /// Steepest slope, in degrees, that mountains keep.
+    pub mountain_talus_angle: f32,
+    /// Steepest slope, in degrees, that dunes keep.
+    pub dune_talus_angle: f32,
+    /// Steepest slope, in degrees, that spires keep.
+    pub spire_talus_angle: f32,
+    /// How much, in degrees, the seed can make the ground harder or softer
+    /// than its biome's talus angle, vertex by vertex.
+    pub angle_variation: f32,
+}
+
+impl Default for ThermalOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 50,
+            rate: 0.25,
+            mountain_talus_angle: 40.0,
+            dune_talus_angle: 33.0,
+            spire_talus_angle: 60.0,
+            angle_variation: 5.0,
+        }
+    }
+}
+
+impl ThermalOptions {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (0.0..=0.5).contains(&self.rate),
+            "rate must be from 0 to 0.5, not {}",
+            self.rate
+        );
+        for (name, value) in [
+            ("mountain_talus_angle", self.mountain_talus_angle),
+            ("dune_talus_angle", self.dune_talus_angle),
+            ("spire_talus_angle", self.spire_talus_angle),
+        ] {
+            anyhow::ensure!(
+                (0.0..90.0).contains(&value),
+                "{name} must be from 0 up to 90 degrees, not {value}"
+            );
+        }
+        anyhow::ensure!(
+            self.angle_variation.is_finite() && self.angle_variation >= 0.0,
+            "angle_variation can't be negative, not {}",
+            self.angle_variation
+        );
+        Ok(())
+    }
+}
+
+/// Weathers the terrain's heights as they're baked, without changing the
+/// terrain. Material only moves between vertices, none is lost. The same
+/// seed weathers the same way.
+pub fn weather(terrain: &Terrain, options: &ThermalOptions, seed: u32) -> HeightChanges {
+    let original = HeightField::bake(terrain);
+    let width = original.width;
+
+    let mut rng = Rng::new(seed);
+    let angles = glam::vec3(
+        options.mountain_talus_angle,
+        options.dune_talus_angle,
+        options.spire_talus_angle,
+    );
+    let talus = (0..width * width)
+        .map(|i| {
+            let p = glam::vec2((i % width) as f32, (i / width) as f32);
+            let angle = terrain.biome_blend(p).truncate().dot(angles)
+                + (rng.next_f32() * 2.0 - 1.0) * options.angle_variation;
+            angle.clamp(0.0, 89.0).to_radians().tan()
+        })
+        .collect::<Vec<_>>();
+
+    let mut field = original.clone();
+    let mut deltas = vec![0.0; field.heights.len()];
+    for _ in 0..options.iterations {
+        // Every vertex sheds based on the heights from the last
+        // iteration, so the order they're visited in doesn't matter
+        deltas.fill(0.0);
+        for z in 0..width as i32 {
+            for x in 0..width as i32 {
+                field.shed(x, z, talus[field.index(x, z)], options.rate, &mut deltas);
+            }
+        }
+        for (height, delta) in field.heights.iter_mut().zip(&deltas) {
+            *height += delta;
+        }
+    }
+
+    field.changes(&original, terrain)
+}
+
+impl HeightField {
+    /// Moves material from vertex `(x, z)` to the neighbours it's steeper
+    /// than `talus` above, more to the steeper ones, adding the changes to
+    /// `deltas`.
+    fn shed(&self, x: i32, z: i32, talus: f32, rate: f32, deltas: &mut [f32]) {
+        let i = self.index(x, z);
+        let height = self.heights[i];
+
+        let mut excess = [0.0; NEIGHBOURS.len()];
+        let mut total = 0.0;
+        let mut steepest = 0.0f32;
+        for (e, &(dx, dz, distance)) in excess.iter_mut().zip(&NEIGHBOURS) {
+            if !self.contains(x + dx, z + dz) {
+                continue;
+            }
+            let drop = height - self.heights[self.index(x + dx, z + dz)];
+            *e = (drop - talus * distance).max(0.0);
+            total += *e;
+            steepest = steepest.max(*e);
+        }
+        if total <= 0.0 {
+            return;
+        }
+
+        let amount = steepest * rate;
+        deltas[i] -= amount;
+        for (e, &(dx, dz, _)) in excess.iter().zip(&NEIGHBOURS) {
+            if *e > 0.0 {
+                deltas[self.index(x + dx, z + dz)] += amount * e / total;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::world::terrain::{biome::Biome, heightfield::tests::fixture};
+
+    /// A column 20 high and 5 vertices wide in the middle of two by two
+    /// tiles, 32 units across.
+    fn column(x: u32, z: u32) -> f32 {
+        let inside = (14..=18).contains(&x) && (14..=18).contains(&z);
+        if inside { 20.0 } else { 0.0 }
+    }
+
+    #[test]
+    fn slopes_settle_at_the_talus_angle() {
+        let mut terrain = fixture(2, 17, Biome::Mountains, column);
+        let before = HeightField::bake(&terrain);
+        let options = ThermalOptions {
+            iterations: 200,
+            mountain_talus_angle: 30.0,
+            ..Default::default()
+        };
+        weather(&terrain, &options, 1).apply(&mut terrain);
+        let after = HeightField::bake(&terrain);
+
+        // Nothing is lost
+        let sum = |field: &HeightField| field.heights.iter().sum::<f32>();
+        assert!((sum(&after) - sum(&before)).abs() < 1e-2);
+
+        // The column slumped into a heap no steeper than about its talus
+        // angle, give or take the variation
+        let top = after.heights.iter().fold(0.0f32, |a, &b| a.max(b));
+        assert!(top < 15.0, "top {top}");
+        let steepest = (0..32)
+            .map(|x| {
+                let i = after.index(x, 16);
+                (after.heights[i] - after.heights[i + 1]).abs()
+            })
+            .fold(0.0f32, f32::max);
+        assert!(steepest < 40f32.to_radians().tan(), "steepest {steepest}");
+    }
+
+    #[test]
+    fn biomes_keep_their_own_angle() {
+        let options = ThermalOptions {
+            iterations: 200,
+            mountain_talus_angle: 30.0,
+            dune_talus_angle: 60.0,
+            ..Default::default()
+        };
+        let top = |biome| {
+            let mut terrain = fixture(2, 17, biome, column);
+            weather(&terrain, &options, 1).apply(&mut terrain);
+            let heights = HeightField::bake(&terrain).heights;
+            heights.into_iter().fold(0.0f32, f32::max)
+        };
+        // Mountains have the shallower angle here, so they slump into a
+        // lower, wider heap
+        let (mountains, dunes) = (top(Biome::Mountains), top(Biome::Dunes));
+        assert!(mountains + 2.0 < dunes, "{mountains} vs {dunes}");
+    }
+    #[test]
+    fn same_seed_same_weathering() {
+        let terrain = fixture(2, 17, Biome::Mountains, column);
+        let options = ThermalOptions::default();
+        let changes = |seed| weather(&terrain, &options, seed).tiles;
+
+        assert_eq!(changes(7), changes(7));
+        assert_ne!(changes(7), changes(8));
+    }
+}
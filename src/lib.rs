@@ -1,6 +1,9 @@
 use winit::event_loop::EventLoop;
 
-use crate::app::App;
+use crate::app::{
+    App,
+    args::{Command, USAGE},
+};
 
 mod app;
 mod game;
@@ -15,9 +18,17 @@ pub fn run() -> anyhow::Result<()> {
         console_log::init_with_level(log::Level::Info).unwrap_throw();
     }
 
+    let args = match Command::from_env()? {
+        Command::Run(args) => args,
+        Command::Help => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+    };
+
     let event_loop = EventLoop::with_user_event().build()?;
     let proxy = event_loop.create_proxy();
-    let mut app = App::new(proxy, "res");
+    let mut app = App::new(proxy, "res", args);
     event_loop.run_app(&mut app)?;
 
     Ok(())